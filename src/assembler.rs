@@ -0,0 +1,844 @@
+// Assembler for a subset of Octo (https://github.com/JohnEarnest/Octo):
+// labels, :const, :alias, :call, :byte, register ops, if ... then,
+// if ... begin ... else ... end, loop ... while ... again, and bare numbers
+// for sprite data. Programs are assembled for 0x200 and, as in Octo, start
+// with a jump to main unless main is the first thing defined.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    // Both start at 1
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+// Address the assembled program is loaded at
+pub const ORIGIN: usize = 0x200;
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    let mut assembler = Assembler::new(tokenize(source));
+    while let Some(token) = assembler.next_token() {
+        assembler.statement(token)?;
+    }
+    assembler.finish()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, message: impl Into<String>) -> AssembleError {
+        AssembleError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+// Whitespace separated words, comments run from # to the end of the line
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or_default();
+        let mut start = None;
+        for (index, c) in code.char_indices().chain([(code.len(), ' ')]) {
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(index),
+                (true, Some(begin)) => {
+                    tokens.push(Token {
+                        text: &code[begin..index],
+                        line: line_index + 1,
+                        column: code[..begin].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+// Where a label's address goes once it's known
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    // low 12 bits of the opcode at the offset
+    Nnn(usize),
+    // the 16 bit word after F000
+    Long(usize),
+}
+
+enum Block<'a> {
+    If {
+        token: Token<'a>,
+        // offset of the jump taken when the condition is false
+        skip_body: usize,
+        // offset of the jump over the else part, once there is one
+        skip_else: Option<usize>,
+    },
+    Loop {
+        token: Token<'a>,
+        start: usize,
+        // offsets of the jumps out of the loop
+        exits: Vec<usize>,
+    },
+}
+
+// A comparison, "skip" opcodes skip the next instruction when it holds
+#[derive(Debug, Clone, Copy)]
+enum Condition {
+    EqualByte(u8, u8),
+    NotEqualByte(u8, u8),
+    EqualRegister(u8, u8),
+    NotEqualRegister(u8, u8),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::EqualByte(x, kk) => Condition::NotEqualByte(x, kk),
+            Condition::NotEqualByte(x, kk) => Condition::EqualByte(x, kk),
+            Condition::EqualRegister(x, y) => Condition::NotEqualRegister(x, y),
+            Condition::NotEqualRegister(x, y) => Condition::EqualRegister(x, y),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+
+    fn skip_opcode(self) -> u16 {
+        let (x, rest) = match self {
+            Condition::EqualByte(x, kk) => (x, 0x3000 | kk as u16),
+            Condition::NotEqualByte(x, kk) => (x, 0x4000 | kk as u16),
+            Condition::EqualRegister(x, y) => (x, 0x5000 | (y as u16) << 4),
+            Condition::NotEqualRegister(x, y) => (x, 0x9000 | (y as u16) << 4),
+            Condition::Key(x) => (x, 0xE09E),
+            Condition::NotKey(x) => (x, 0xE0A1),
+        };
+        rest | (x as u16) << 8
+    }
+}
+
+struct Assembler<'a> {
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    output: Vec<u8>,
+    labels: HashMap<&'a str, usize>,
+    constants: HashMap<&'a str, i64>,
+    aliases: HashMap<&'a str, u8>,
+    fixups: Vec<(Fixup, Token<'a>)>,
+    blocks: Vec<Block<'a>>,
+    // the first two bytes are a jump to main
+    main_jump: bool,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(tokens: Vec<Token<'a>>) -> Assembler<'a> {
+        Assembler {
+            tokens,
+            position: 0,
+            output: vec![0x10, 0x00],
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            main_jump: true,
+        }
+    }
+
+    fn here(&self) -> usize {
+        ORIGIN + self.output.len()
+    }
+
+    fn next_token(&mut self) -> Option<Token<'a>> {
+        let token = self.tokens.get(self.position).copied();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).map(|token| token.text)
+    }
+
+    // The next token, or an error pointing after the last one
+    fn expect(&mut self, what: &str) -> Result<Token<'a>, AssembleError> {
+        self.next_token().ok_or_else(|| {
+            let last = self.tokens.last().copied().unwrap_or(Token {
+                text: "",
+                line: 1,
+                column: 1,
+            });
+            AssembleError {
+                line: last.line,
+                column: last.column + last.text.chars().count(),
+                message: format!("Expected {} at the end of the file", what),
+            }
+        })
+    }
+
+    fn expect_text(&mut self, text: &str) -> Result<Token<'a>, AssembleError> {
+        let token = self.expect(&format!("'{}'", text))?;
+        if token.text != text {
+            return Err(token.error(format!("Expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn emit(&mut self, opcode: u16) {
+        self.output.extend_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn statement(&mut self, token: Token<'a>) -> Result<(), AssembleError> {
+        match token.text {
+            ":" => {
+                let name = self.expect("a label name")?;
+                self.define_label(name)?;
+            }
+            ":const" => {
+                let name = self.identifier("a constant name")?;
+                let value = self.expect("a value")?;
+                let value = self.value(value)?;
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.identifier("an alias name")?;
+                let register = self.expect("a register")?;
+                let register = self.register(register)?;
+                self.aliases.insert(name.text, register);
+            }
+            ":call" => {
+                let target = self.expect("an address")?;
+                self.emit_with_address(0x2000, target)?;
+            }
+            ":byte" => {
+                let value = self.expect("a value")?;
+                let value = self.byte(value)?;
+                self.output.push(value);
+            }
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "scroll-down" | "scroll-up" => {
+                let n = self.expect("a number of pixels")?;
+                let n = self.ranged(n, 1, 15)?;
+                let base = if token.text == "scroll-down" {
+                    0x00C0
+                } else {
+                    0x00D0
+                };
+                self.emit(base | n);
+            }
+            "audio" => self.emit(0xF002),
+            "plane" => {
+                let n = self.expect("a plane mask")?;
+                let n = self.ranged(n, 0, 3)?;
+                self.emit(0xF001 | n << 8);
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.expect("a register")?;
+                let x = self.register(x)? as u16;
+                let low = match token.text {
+                    "bcd" => 0x33,
+                    "saveflags" => 0x75,
+                    _ => 0x85,
+                };
+                self.emit(0xF000 | x << 8 | low);
+            }
+            "save" | "load" => {
+                let x = self.expect("a register")?;
+                let x = self.register(x)? as u16;
+                if self.peek() == Some("-") {
+                    self.next_token();
+                    let y = self.expect("a register")?;
+                    let y = self.register(y)? as u16;
+                    let n = if token.text == "save" { 2 } else { 3 };
+                    self.emit(0x5000 | x << 8 | y << 4 | n);
+                } else {
+                    let low = if token.text == "save" { 0x55 } else { 0x65 };
+                    self.emit(0xF000 | x << 8 | low);
+                }
+            }
+            "sprite" => {
+                let x = self.expect("a register")?;
+                let x = self.register(x)? as u16;
+                let y = self.expect("a register")?;
+                let y = self.register(y)? as u16;
+                let n = self.expect("a height")?;
+                let n = self.ranged(n, 0, 15)?;
+                self.emit(0xD000 | x << 8 | y << 4 | n);
+            }
+            "jump" | "jump0" => {
+                let target = self.expect("an address")?;
+                let base = if token.text == "jump" { 0x1000 } else { 0xB000 };
+                self.emit_with_address(base, target)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect_text(":=")?;
+                let x = self.expect("a register")?;
+                let x = self.register(x)? as u16;
+                let low = match token.text {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.emit(0xF000 | x << 8 | low);
+            }
+            "i" => self.i_statement()?,
+            "if" => self.if_statement(token)?,
+            "else" => match self.blocks.last_mut() {
+                Some(Block::If {
+                    skip_else: skip_else @ None,
+                    skip_body,
+                    ..
+                }) => {
+                    let skip_body = *skip_body;
+                    *skip_else = Some(self.output.len());
+                    self.emit(0x1000);
+                    self.patch(Fixup::Nnn(skip_body), self.here(), token)?;
+                }
+                _ => return Err(token.error("'else' without 'if ... begin'")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If {
+                    skip_body,
+                    skip_else,
+                    ..
+                }) => {
+                    let jump = skip_else.unwrap_or(skip_body);
+                    self.patch(Fixup::Nnn(jump), self.here(), token)?;
+                }
+                _ => return Err(token.error("'end' without 'if ... begin'")),
+            },
+            "loop" => {
+                let start = self.here();
+                self.blocks.push(Block::Loop {
+                    token,
+                    start,
+                    exits: Vec::new(),
+                });
+            }
+            "while" => {
+                let condition = self.condition()?;
+                let exit = self.output.len() + 2;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|b| matches!(b, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { exits, .. }) => exits.push(exit),
+                    _ => return Err(token.error("'while' outside of a loop")),
+                }
+                // leaves the loop when the condition doesn't hold
+                self.emit(condition.skip_opcode());
+                self.emit(0x1000);
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits, .. }) => {
+                    self.emit(0x1000 | start as u16);
+                    for exit in exits {
+                        self.patch(Fixup::Nnn(exit), self.here(), token)?;
+                    }
+                }
+                _ => return Err(token.error("'again' without 'loop'")),
+            },
+            _ if self.is_register(token.text) => self.register_statement(token)?,
+            _ if is_number(token.text) || self.constants.contains_key(token.text) => {
+                let value = self.byte(token)?;
+                self.output.push(value);
+            }
+            // anything else is a subroutine call
+            _ if is_identifier(token.text) => self.emit_with_address(0x2000, token)?,
+            _ => return Err(token.error(format!("Unexpected '{}'", token.text))),
+        }
+
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: Token<'a>) -> Result<(), AssembleError> {
+        if !is_identifier(name.text) {
+            return Err(name.error(format!("Invalid label name '{}'", name.text)));
+        }
+        if self.labels.contains_key(name.text) {
+            return Err(name.error(format!("Label '{}' is already defined", name.text)));
+        }
+
+        // main right at the start doesn't need the jump
+        if name.text == "main" && self.main_jump && self.output.len() == 2 {
+            self.main_jump = false;
+            self.output.clear();
+            for address in self.labels.values_mut() {
+                *address = ORIGIN;
+            }
+        }
+
+        self.labels.insert(name.text, self.here());
+        Ok(())
+    }
+
+    fn i_statement(&mut self) -> Result<(), AssembleError> {
+        let op = self.expect("':=' or '+='")?;
+        match op.text {
+            "+=" => {
+                let x = self.expect("a register")?;
+                let x = self.register(x)? as u16;
+                self.emit(0xF01E | x << 8);
+            }
+            ":=" => {
+                let value = self.expect("an address")?;
+                match value.text {
+                    "hex" | "bighex" => {
+                        let x = self.expect("a register")?;
+                        let x = self.register(x)? as u16;
+                        let low = if value.text == "hex" { 0x29 } else { 0x30 };
+                        self.emit(0xF000 | x << 8 | low);
+                    }
+                    "long" => {
+                        let target = self.expect("an address")?;
+                        self.emit(0xF000);
+                        let offset = self.output.len();
+                        self.emit(0x0000);
+                        self.address(Fixup::Long(offset), target)?;
+                    }
+                    _ => self.emit_with_address(0xA000, value)?,
+                }
+            }
+            _ => return Err(op.error(format!("Expected ':=' or '+=', found '{}'", op.text))),
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self, token: Token<'a>) -> Result<(), AssembleError> {
+        let condition = self.condition()?;
+        let keyword = self.expect("'then' or 'begin'")?;
+        match keyword.text {
+            // skips the next instruction when the condition doesn't hold
+            "then" => self.emit(condition.negate().skip_opcode()),
+            "begin" => {
+                self.emit(condition.skip_opcode());
+                let skip_body = self.output.len();
+                self.emit(0x1000);
+                self.blocks.push(Block::If {
+                    token,
+                    skip_body,
+                    skip_else: None,
+                });
+            }
+            _ => {
+                return Err(keyword.error(format!(
+                    "Expected 'then' or 'begin', found '{}'",
+                    keyword.text
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.expect("a register")?;
+        let x = self.register(x)?;
+        let op = self.expect("a comparison")?;
+        let condition = match op.text {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            "==" | "!=" => {
+                let rhs = self.expect("a register or a value")?;
+                let equal = op.text == "==";
+                match (self.is_register(rhs.text), equal) {
+                    (true, true) => Condition::EqualRegister(x, self.register(rhs)?),
+                    (true, false) => Condition::NotEqualRegister(x, self.register(rhs)?),
+                    (false, true) => Condition::EqualByte(x, self.byte(rhs)?),
+                    (false, false) => Condition::NotEqualByte(x, self.byte(rhs)?),
+                }
+            }
+            _ => {
+                return Err(op.error(format!(
+                    "Unsupported comparison '{}', expected ==, !=, key or -key",
+                    op.text
+                )))
+            }
+        };
+        Ok(condition)
+    }
+
+    fn register_statement(&mut self, token: Token<'a>) -> Result<(), AssembleError> {
+        let x = self.register(token)? as u16;
+        let op = self.expect("an operator")?;
+        let rhs = self.expect("a value")?;
+
+        let opcode = match (op.text, rhs.text) {
+            (":=", "random") => {
+                let mask = self.expect("a mask")?;
+                0xC000 | self.byte(mask)? as u16
+            }
+            (":=", "key") => 0xF00A,
+            (":=", "delay") => 0xF007,
+            _ if self.is_register(rhs.text) => {
+                let y = (self.register(rhs)? as u16) << 4;
+                let n = match op.text {
+                    ":=" => 0x0,
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(op.error(format!("Unknown operator '{}'", op.text))),
+                };
+                0x8000 | y | n
+            }
+            (":=", _) => 0x6000 | self.byte(rhs)? as u16,
+            ("+=", _) => 0x7000 | self.byte(rhs)? as u16,
+            // there's no subtract byte opcode, add its two's complement
+            ("-=", _) => 0x7000 | self.byte(rhs)?.wrapping_neg() as u16,
+            _ => {
+                return Err(op.error(format!(
+                    "Operator '{}' needs a register on the right",
+                    op.text
+                )))
+            }
+        };
+
+        self.emit(opcode | x << 8);
+        Ok(())
+    }
+
+    fn emit_with_address(&mut self, opcode: u16, target: Token<'a>) -> Result<(), AssembleError> {
+        let offset = self.output.len();
+        self.emit(opcode);
+        self.address(Fixup::Nnn(offset), target)
+    }
+
+    // Numbers and constants are written right away, labels once they're known
+    fn address(&mut self, fixup: Fixup, target: Token<'a>) -> Result<(), AssembleError> {
+        if is_number(target.text) || self.constants.contains_key(target.text) {
+            let value = self.value(target)?;
+            let address = usize::try_from(value)
+                .map_err(|_| target.error(format!("Invalid address {}", value)))?;
+            return self.patch(fixup, address, target);
+        }
+        if !is_identifier(target.text) {
+            return Err(target.error(format!("Expected an address, found '{}'", target.text)));
+        }
+
+        match self.labels.get(target.text) {
+            Some(&address) => self.patch(fixup, address, target),
+            None => {
+                self.fixups.push((fixup, target));
+                Ok(())
+            }
+        }
+    }
+
+    fn patch(&mut self, fixup: Fixup, address: usize, token: Token) -> Result<(), AssembleError> {
+        match fixup {
+            Fixup::Nnn(offset) => {
+                if address > 0xFFF {
+                    return Err(token.error(format!(
+                        "Address {:#X} doesn't fit in 12 bits, use i := long",
+                        address
+                    )));
+                }
+                self.output[offset] = (self.output[offset] & 0xF0) | (address >> 8) as u8;
+                self.output[offset + 1] = address as u8;
+            }
+            Fixup::Long(offset) => {
+                if address > 0xFFFF {
+                    return Err(
+                        token.error(format!("Address {:#X} doesn't fit in 16 bits", address))
+                    );
+                }
+                self.output[offset..offset + 2].copy_from_slice(&(address as u16).to_be_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AssembleError> {
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::If { token, .. } => token.error("'if ... begin' without 'end'"),
+                Block::Loop { token, .. } => token.error("'loop' without 'again'"),
+            });
+        }
+
+        if self.main_jump {
+            let main = *self.labels.get("main").ok_or(AssembleError {
+                line: 1,
+                column: 1,
+                message: "The program has no main label".to_string(),
+            })?;
+            self.output[..2].copy_from_slice(&(0x1000 | main as u16).to_be_bytes());
+        }
+
+        for (fixup, token) in std::mem::take(&mut self.fixups) {
+            let address = *self
+                .labels
+                .get(token.text)
+                .ok_or_else(|| token.error(format!("Undefined label '{}'", token.text)))?;
+            self.patch(fixup, address, token)?;
+        }
+
+        Ok(self.output)
+    }
+
+    fn identifier(&mut self, what: &str) -> Result<Token<'a>, AssembleError> {
+        let token = self.expect(what)?;
+        if !is_identifier(token.text) {
+            return Err(token.error(format!("Expected {}, found '{}'", what, token.text)));
+        }
+        Ok(token)
+    }
+
+    fn is_register(&self, text: &str) -> bool {
+        parse_register(text).is_some() || self.aliases.contains_key(text)
+    }
+
+    fn register(&self, token: Token) -> Result<u8, AssembleError> {
+        parse_register(token.text)
+            .or(self.aliases.get(token.text).copied())
+            .ok_or_else(|| token.error(format!("Expected a register, found '{}'", token.text)))
+    }
+
+    fn value(&self, token: Token) -> Result<i64, AssembleError> {
+        if let Some(&value) = self.constants.get(token.text) {
+            return Ok(value);
+        }
+        parse_number(token.text)
+            .ok_or_else(|| token.error(format!("Expected a number, found '{}'", token.text)))
+    }
+
+    // Negative values are written in two's complement
+    fn byte(&self, token: Token) -> Result<u8, AssembleError> {
+        let value = self.value(token)?;
+        match value {
+            -128..=255 => Ok(value as u8),
+            _ => Err(token.error(format!("{} doesn't fit in a byte", value))),
+        }
+    }
+
+    fn ranged(&self, token: Token, min: i64, max: i64) -> Result<u16, AssembleError> {
+        let value = self.value(token)?;
+        if value < min || value > max {
+            return Err(token.error(format!("{} isn't between {} and {}", value, min, max)));
+        }
+        Ok(value as u16)
+    }
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix('v').or(text.strip_prefix('V'))?;
+    match digit.len() {
+        1 => u8::from_str_radix(digit, 16).ok(),
+        _ => None,
+    }
+}
+
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_number(text: &str) -> bool {
+    parse_number(text).is_some()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !KEYWORDS.contains(&text)
+}
+
+const KEYWORDS: [&str; 32] = [
+    "clear",
+    "return",
+    "exit",
+    "lores",
+    "hires",
+    "scroll-right",
+    "scroll-left",
+    "scroll-down",
+    "scroll-up",
+    "audio",
+    "plane",
+    "bcd",
+    "saveflags",
+    "loadflags",
+    "save",
+    "load",
+    "sprite",
+    "jump",
+    "jump0",
+    "delay",
+    "buzzer",
+    "pitch",
+    "i",
+    "if",
+    "then",
+    "begin",
+    "else",
+    "end",
+    "loop",
+    "while",
+    "again",
+    "key",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::InstructionSet;
+    use crate::disasm;
+
+    #[test]
+    fn program() {
+        let source = "
+            :alias x v1
+            :const SPEED 2
+
+            : ball
+                0b11000000
+                0b11000000
+
+            : main
+                clear
+                x := 0
+                i := ball
+                loop
+                    sprite x v2 2
+                    x += SPEED
+                    if x == 60 then x := 0
+                    while v3 -key
+                again
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x12, 0x04, // jump main
+                0xC0, 0xC0, // ball
+                0x00, 0xE0, // clear
+                0x61, 0x00, // x := 0
+                0xA2, 0x02, // i := ball
+                0xD1, 0x22, // sprite
+                0x71, 0x02, // x += SPEED
+                0x41, 0x3C, // if x == 60 then
+                0x61, 0x00, // x := 0
+                0xE3, 0xA1, // while v3 -key
+                0x12, 0x18, // leave the loop
+                0x12, 0x0A, // again
+            ]
+        );
+    }
+
+    #[test]
+    fn blocks() {
+        let source = "
+            : main
+                if v0 != v1 begin
+                    v0 -= 1
+                else
+                    v0 >>= v0
+                end
+                i := long data
+                return
+            : data
+        ";
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            rom,
+            [
+                0x90, 0x10, // skip the jump when v0 != v1
+                0x12, 0x08, // to else
+                0x70, 0xFF, // v0 -= 1
+                0x12, 0x0A, // to end
+                0x80, 0x06, // v0 >>= v0
+                0xF0, 0x00, 0x02, 0x10, // i := long data
+                0x00, 0xEE,
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = assemble(": main\n  v0 := 300").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 9));
+        assert_eq!(
+            error.to_string(),
+            "line 2, column 9: 300 doesn't fit in a byte"
+        );
+
+        let error = assemble(": main\n\tjump nowhere").unwrap_err();
+        assert_eq!((error.line, error.column), (2, 7));
+        assert_eq!(error.message, "Undefined label 'nowhere'");
+
+        let error = assemble(": main loop v0 += 1").unwrap_err();
+        assert_eq!((error.line, error.column), (1, 8));
+
+        assert!(assemble("clear").unwrap_err().message.contains("main"));
+        assert!(assemble(": main if v0 < 3 then clear").is_err());
+        assert!(assemble(": main else").is_err());
+    }
+
+    #[test]
+    fn disassembly_reassembles() {
+        for dir in ["roms", "tests_roms"] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                let extension = path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default();
+                if !extension.eq_ignore_ascii_case("ch8") {
+                    continue;
+                }
+
+                let rom = std::fs::read(&path).unwrap();
+                let listing = disasm::disassemble(&rom, ORIGIN, InstructionSet::XoChip);
+                let source = listing.to_string();
+                assert_eq!(assemble(&source).unwrap(), rom, "{}", path.display());
+            }
+        }
+    }
+}
@@ -1,19 +1,30 @@
-use sdl2::EventPump;
-use sdl2::{render::WindowCanvas, AudioSubsystem};
 use std::{
-    io::{self, BufReader, Read},
-    path::Path,
+    fs,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
     thread::sleep,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use rand::Rng;
-
-use crate::config::Config;
-use crate::{display::Display, keypad::Keypad, speaker::Speaker};
+use crate::assembler;
+use crate::config::{Config, InstructionSet, OpcodePolicy};
+use crate::database::{self, Database, RomMetadata};
+use crate::debugger::{self, Debugger};
+use crate::detect::{self, Detection};
+use crate::display::{ColorLayer, Display, Palette};
+use crate::error::ChipError;
+use crate::frontend::{Audio, Input, InputEvent, Video};
+use crate::keypad::Keypad;
+use crate::movie::{self, Movie, MovieInput, MovieState};
+use crate::random::Random;
+use crate::rewind::Rewind;
+use crate::savestate::{self, SaveStateError, StateReader, StateWriter};
+use crate::trace::{self, Tracer};
+use crate::watch::{Location, WatchHit, Watchpoint};
 
 #[derive(Debug)]
 struct Instruction {
+    raw: u16,
     parts: (u8, u8, u8, u8),
     nnn: u16,
     n: u8,
@@ -38,6 +49,7 @@ impl Instruction {
         // kk or byte - An 8-bit value, the lowest 8 bits of the instruction
 
         Instruction {
+            raw: instruction,
             parts,
             nnn: instruction & 0x0FFF,
             n: parts.3,
@@ -48,13 +60,39 @@ impl Instruction {
     }
 }
 
+// Result of running a single frame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameOutput {
+    pub width: usize,
+    pub height: usize,
+    // One u128 per row, the most significant bit is the leftmost pixel.
+    // The second plane is only drawn on by XO-CHIP programs.
+    pub planes: [Vec<u128>; Display::PLANES],
+    pub sound: bool,
+    pub instructions: u64,
+}
+
+// How the chip touches memory, fetching code doesn't trigger watchpoints
+#[derive(Clone, Copy)]
+enum MemoryAccess {
+    Fetch,
+    Read,
+    Write(u8),
+}
+
 pub struct Chip {
     config: Config,
     waiting_key: bool,
     drew_on_frame: bool,
-    rng: rand::rngs::ThreadRng,
+    // instructions owed to the next frame, in 1/TIMER_HZ units
+    cycle_remainder: u64,
+    unknown_opcodes: u64,
+    rng: Random,
     keep_running: bool,
-    ram: [u8; Chip::RAM_SIZE],
+    // SUPER-CHIP persistent flags, saved and restored with Fx75 and Fx85
+    rpl_flags: [u8; Chip::GENERAL_REGISTERS],
+    // always XO_RAM_SIZE bytes, only memory_size() of them are addressable
+    ram: Vec<u8>,
     stack: [u16; Chip::STACK_SIZE],
     regs: [u8; Chip::GENERAL_REGISTERS],
     // original u16
@@ -66,7 +104,26 @@ pub struct Chip {
     sp_reg: usize,
     display: Display,
     keypad: Keypad,
-    speaker: Speaker,
+    // XO-CHIP audio pattern, None plays the default tone
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    // CHIP-8X I/O port, last value written by FxF8 and value FxFB is waiting for
+    io_output: u8,
+    io_input: Option<u8>,
+    // SHA-1 of the loaded ROM, save states are tied to it
+    rom_hash: Option<String>,
+    // What the program database knows about the loaded ROM
+    metadata: Option<RomMetadata>,
+    // Platform guessed from the opcodes of ROMs the database doesn't know
+    detection: Option<Detection>,
+    watchpoints: Vec<Watchpoint>,
+    // what the last instruction did to the watched memory and registers
+    watch_hits: Vec<WatchHit>,
+    tracer: Option<Tracer>,
+    movie: Option<MovieState>,
+    // instructions run and frames emulated since the chip was created
+    cycle_count: u64,
+    frame_count: u64,
 }
 
 impl std::fmt::Debug for Chip {
@@ -90,7 +147,7 @@ impl std::fmt::Debug for Chip {
         writeln!(f)?;
 
         writeln!(f, "Ram: ")?;
-        for (i, b) in self.ram.iter().enumerate() {
+        for (i, b) in self.ram[..self.memory_size()].iter().enumerate() {
             if i % 32 == 0 {
                 writeln!(f)?;
                 write!(f, "{} | ", i)?;
@@ -101,25 +158,39 @@ impl std::fmt::Debug for Chip {
     }
 }
 
+impl Default for Chip {
+    fn default() -> Self {
+        Chip::new()
+    }
+}
+
 impl Chip {
     const RAM_SIZE: usize = 4096;
+    const XO_RAM_SIZE: usize = 0x10000;
     const STACK_SIZE: usize = 16;
     const GENERAL_REGISTERS: usize = 16;
     const PROGRAM_START: usize = 512;
+    const CHIP8X_PROGRAM_START: usize = 0x300;
     const BYTES_PER_SPRITE: u8 = 5;
+    const BIG_FONT_START: usize = 0x50;
+    const BYTES_PER_BIG_SPRITE: u8 = 10;
+    const DEFAULT_PITCH: u8 = 64;
+
+    pub fn new() -> Chip {
+        Chip::with_config(Config::new())
+    }
 
-    pub fn new(
-        canvas: WindowCanvas,
-        event_queue: EventPump,
-        audio_subsystem: AudioSubsystem,
-    ) -> Chip {
+    pub fn with_config(config: Config) -> Chip {
         let mut chip = Chip {
-            config: Config::new(),
+            rng: Random::new(config.seed),
+            config,
             waiting_key: false,
             drew_on_frame: false,
+            cycle_remainder: 0,
+            unknown_opcodes: 0,
             keep_running: true,
-            rng: rand::thread_rng(),
-            ram: [0; Chip::RAM_SIZE],
+            rpl_flags: [0; Chip::GENERAL_REGISTERS],
+            ram: vec![0; Chip::XO_RAM_SIZE],
             stack: [0; Chip::STACK_SIZE],
             regs: [0; Chip::GENERAL_REGISTERS],
             i_reg: 0,
@@ -127,9 +198,21 @@ impl Chip {
             sound_reg: 0,
             pc_reg: Chip::PROGRAM_START,
             sp_reg: 0,
-            display: Display::new(canvas),
-            keypad: Keypad::new(event_queue),
-            speaker: Speaker::new(audio_subsystem),
+            display: Display::new(),
+            keypad: Keypad::new(),
+            audio_pattern: None,
+            pitch: Chip::DEFAULT_PITCH,
+            io_output: 0,
+            io_input: None,
+            rom_hash: None,
+            metadata: None,
+            detection: None,
+            watchpoints: Vec::new(),
+            watch_hits: Vec::new(),
+            tracer: None,
+            movie: None,
+            cycle_count: 0,
+            frame_count: 0,
         };
 
         let sprites = vec![
@@ -153,79 +236,828 @@ impl Chip {
 
         chip.ram[0..sprites.len()].copy_from_slice(&sprites);
 
+        let big_sprites = vec![
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+        ];
+
+        chip.ram[Chip::BIG_FONT_START..Chip::BIG_FONT_START + big_sprites.len()]
+            .copy_from_slice(&big_sprites);
+
         chip
     }
 
-    pub fn load(&mut self, rom_path: &Path) -> Result<String, String> {
-        let file = std::fs::File::open(rom_path);
-        if let Err(_) = file {
-            return Err(String::from("Error opening ROM file"));
-        }
+    pub fn load(&mut self, rom_path: &Path) -> Result<(), ChipError> {
+        let file = std::fs::File::open(rom_path)?;
 
-        let mut file_reader = BufReader::new(file.unwrap());
+        let mut file_reader = BufReader::new(file);
         let mut buffer = Vec::new();
-        if let Err(_) = file_reader.read_to_end(&mut buffer) {
-            return Err(String::from("Error reading ROM file"));
+        file_reader.read_to_end(&mut buffer)?;
+
+        let extension = rom_path.extension().and_then(|e| e.to_str());
+        // Octo source is assembled first
+        if extension == Some("8o") {
+            let source = String::from_utf8_lossy(&buffer);
+            let rom = assembler::assemble(&source)?;
+            return self.load_rom_with_extension(&rom, extension);
+        }
+
+        self.load_rom_with_extension(&buffer, extension)
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), ChipError> {
+        self.load_rom_with_extension(rom, None)
+    }
+
+    // The extension is a hint for platform detection, when the ROM isn't in the database
+    fn load_rom_with_extension(
+        &mut self,
+        rom: &[u8],
+        extension: Option<&str>,
+    ) -> Result<(), ChipError> {
+        // the platform decides how much memory there is
+        let database = Database::get();
+        let hash = database::sha1(rom);
+        self.metadata = database.find(&hash);
+        self.rom_hash = Some(hash.clone());
+
+        let mut user_platform = database.platform_override(&hash);
+        self.detection = None;
+        if self.metadata.is_none() && user_platform.is_none() && !self.config.is_platform_forced() {
+            let mut detection = detect::detect(rom, extension, self.program_start());
+            // a CHIP-8X guess loads the program at 0x300, so that's where it's
+            // scanned from
+            if detection.platform == "chip8x" && self.program_start() != Chip::CHIP8X_PROGRAM_START
+            {
+                let rescan = detect::detect(rom, extension, Chip::CHIP8X_PROGRAM_START);
+                if rescan.platform == "chip8x" {
+                    detection = rescan;
+                }
+            }
+            if detection.is_confident() {
+                user_platform = Some(detection.platform);
+            }
+            self.detection = Some(detection);
+        }
+        self.config
+            .adjust_to_rom(self.metadata.as_ref(), user_platform);
+
+        let start = self.program_start();
+        let max = self.memory_size() - start;
+        if rom.len() > max {
+            return Err(ChipError::RomTooLarge {
+                size: rom.len(),
+                max,
+            });
+        }
+
+        self.ram[start..(start + rom.len())].copy_from_slice(rom);
+        self.pc_reg = start;
+        // a new ROM starts with the buzzer, not the last one's pattern
+        self.audio_pattern = None;
+        self.pitch = Chip::DEFAULT_PITCH;
+
+        if self.config.instruction_set.has_chip8x() {
+            self.display.enable_color_layer();
+        }
+
+        if let Some(colors) = self.metadata.as_ref().and_then(|m| m.rom.colors.as_ref()) {
+            self.display.set_palette(Palette::from_colors(colors));
+        }
+
+        Ok(())
+    }
+
+    // The CHIP-8X interpreter takes more room, so programs start at 0x300
+    pub fn program_start(&self) -> usize {
+        if self.config.instruction_set.has_chip8x() {
+            Chip::CHIP8X_PROGRAM_START
+        } else {
+            Chip::PROGRAM_START
+        }
+    }
+
+    // Last value the ROM wrote to the CHIP-8X I/O port
+    pub fn io_output(&self) -> u8 {
+        self.io_output
+    }
+
+    // Value FxFB will read from the CHIP-8X I/O port
+    pub fn set_io_input(&mut self, value: u8) {
+        self.io_input = Some(value);
+    }
+
+    pub fn memory_size(&self) -> usize {
+        Chip::memory_size_for(self.config.instruction_set)
+    }
+
+    fn memory_size_for(instruction_set: InstructionSet) -> usize {
+        if instruction_set.has_xochip() {
+            Chip::XO_RAM_SIZE
+        } else {
+            Chip::RAM_SIZE
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut Config {
+        &mut self.config
+    }
+
+    // Number of opcodes found so far that the interpreter doesn't recognise.
+    // A high count usually means the ROM targets a platform we don't emulate.
+    pub fn unknown_opcodes(&self) -> u64 {
+        self.unknown_opcodes
+    }
+
+    // False once the ROM exits with 00FD or the frontend asks to quit
+    pub fn is_running(&self) -> bool {
+        self.keep_running
+    }
+
+    // Makes run return after the current frame
+    pub fn stop(&mut self) {
+        self.keep_running = false;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc_reg as u16
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i_reg as u16
+    }
+
+    pub fn sp(&self) -> usize {
+        self.sp_reg
+    }
+
+    pub fn registers(&self) -> &[u8; Chip::GENERAL_REGISTERS] {
+        &self.regs
+    }
+
+    // Return addresses of the calls in progress, the innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp_reg]
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_reg
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_reg
+    }
+
+    // The addressable memory, memory_size() bytes
+    pub fn memory(&self) -> &[u8] {
+        &self.ram[..self.memory_size()]
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w != watchpoint);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // The whole machine, config and random generator included, tied to the ROM
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        self.config.save_state(&mut w);
+        self.rng.save_state(&mut w);
+        w.u32(self.pc_reg as u32);
+        w.u32(self.i_reg as u32);
+        w.u8(self.sp_reg as u8);
+        w.bytes(&self.regs);
+        for address in self.stack {
+            w.u16(address);
+        }
+        w.u8(self.delay_reg);
+        w.u8(self.sound_reg);
+        w.bool(self.waiting_key);
+        w.bool(self.drew_on_frame);
+        w.u64(self.cycle_remainder);
+        w.u64(self.cycle_count);
+        w.u64(self.frame_count);
+        w.bytes(&self.rpl_flags);
+        w.u32(self.memory_size() as u32);
+        w.bytes(self.memory());
+        w.option(self.audio_pattern, |w, pattern| w.bytes(&pattern));
+        w.u8(self.pitch);
+        w.u8(self.io_output);
+        w.option(self.io_input, StateWriter::u8);
+        self.display.save_state(&mut w);
+        self.keypad.save_state(&mut w);
+
+        savestate::encode(
+            self.rom_hash.as_deref().unwrap_or_default(),
+            &w.into_bytes(),
+        )
+    }
+
+    // Restores a state made by save_state for the same ROM. Everything is
+    // decoded and checked first, a bad state leaves the chip as it was.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), ChipError> {
+        let payload = savestate::decode(bytes, self.rom_hash.as_deref().unwrap_or_default())?;
+        let mut r = StateReader::new(payload);
+        let config = self.config.load_state(&mut r)?;
+        let rng = Random::load_state(&mut r)?;
+        let pc_reg = r.u32()? as usize;
+        let i_reg = r.u32()? as usize;
+        let sp_reg = r.u8()? as usize;
+        if sp_reg > Chip::STACK_SIZE {
+            return Err(SaveStateError::Invalid("stack pointer").into());
+        }
+        let regs = r.array()?;
+        let mut stack = [0; Chip::STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = r.u16()?;
+        }
+        let delay_reg = r.u8()?;
+        let sound_reg = r.u8()?;
+        let waiting_key = r.bool()?;
+        let drew_on_frame = r.bool()?;
+        let cycle_remainder = r.u64()?;
+        let cycle_count = r.u64()?;
+        let frame_count = r.u64()?;
+        let rpl_flags = r.array()?;
+        let memory_size = r.u32()? as usize;
+        if memory_size != Chip::memory_size_for(config.instruction_set) {
+            return Err(SaveStateError::Invalid("memory size").into());
+        }
+        let memory = r.bytes(memory_size)?;
+        let audio_pattern = r.option(StateReader::array)?;
+        let pitch = r.u8()?;
+        let io_output = r.u8()?;
+        let io_input = r.option(StateReader::u8)?;
+        let display = self.display.load_state(&mut r)?;
+        let keypad = self.keypad.load_state(&mut r)?;
+
+        if !r.is_empty() {
+            return Err(SaveStateError::Invalid("length").into());
+        }
+
+        self.config = config;
+        self.rng = rng;
+        self.pc_reg = pc_reg;
+        self.i_reg = i_reg;
+        self.sp_reg = sp_reg;
+        self.regs = regs;
+        self.stack = stack;
+        self.delay_reg = delay_reg;
+        self.sound_reg = sound_reg;
+        self.waiting_key = waiting_key;
+        self.drew_on_frame = drew_on_frame;
+        self.cycle_remainder = cycle_remainder;
+        self.cycle_count = cycle_count;
+        self.frame_count = frame_count;
+        self.rpl_flags = rpl_flags;
+        self.ram[..memory_size].copy_from_slice(memory);
+        self.audio_pattern = audio_pattern;
+        self.pitch = pitch;
+        self.io_output = io_output;
+        self.io_input = io_input;
+        self.display = display;
+        self.keypad = keypad;
+        Ok(())
+    }
+
+    pub fn rom_hash(&self) -> Option<&str> {
+        self.rom_hash.as_deref()
+    }
+
+    // Records the inputs from now on. Has to start before the first frame;
+    // without a seed in the config one is picked, so the movie has one.
+    pub fn start_recording(&mut self) -> Result<(), ChipError> {
+        let hash = self.movie_start_check()?.to_string();
+        let seed = self.config.seed.unwrap_or_else(rand::random);
+        self.config.seed = Some(seed);
+        self.rng = Random::new(Some(seed));
+        self.movie = Some(MovieState::Recording(Movie::new(&hash, &self.config, seed)));
+        Ok(())
+    }
+
+    // Plays the movie's inputs back, in place of the player's. The ROM has to
+    // be loaded for the platform the movie was recorded on.
+    pub fn start_replay(&mut self, movie: Movie) -> Result<(), ChipError> {
+        let hash = self.movie_start_check()?;
+        if movie.rom_sha1 != hash {
+            return Err(ChipError::Movie(format!(
+                "it was recorded with the ROM with SHA-1 {}, not {}",
+                movie.rom_sha1, hash
+            )));
+        }
+        if movie.platform != self.config.platform {
+            return Err(ChipError::Movie(format!(
+                "it was recorded on the {} platform",
+                movie.platform.as_deref().unwrap_or("default")
+            )));
+        }
+
+        self.config.ips = movie.ips;
+        self.config.set_quirks(movie.quirks);
+        self.config.seed = Some(movie.seed);
+        self.rng = Random::new(Some(movie.seed));
+        self.movie = Some(MovieState::Replaying { movie, next: 0 });
+        Ok(())
+    }
+
+    fn movie_start_check(&self) -> Result<&str, ChipError> {
+        if self.frame_count > 0 {
+            return Err(ChipError::Movie(
+                "movies start before the first frame".to_string(),
+            ));
+        }
+        self.rom_hash
+            .as_deref()
+            .ok_or_else(|| ChipError::Movie("no ROM loaded".to_string()))
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.movie.as_ref().is_some_and(MovieState::is_replaying)
+    }
+
+    // Stops recording and returns the movie, with the hash of the last frame
+    pub fn finish_recording(&mut self) -> Option<Movie> {
+        match self.movie.take() {
+            Some(MovieState::Recording(mut movie)) => {
+                movie.frames = self.frame_count;
+                movie.final_hash = movie::frame_hash(&self.display);
+                Some(movie)
+            }
+            other => {
+                self.movie = other;
+                None
+            }
+        }
+    }
+
+    // Once a replay has run all its frames, whether the display ended up as
+    // it was recorded
+    pub fn replay_result(&self) -> Option<bool> {
+        match &self.movie {
+            Some(MovieState::Replaying { movie, .. }) if self.frame_count >= movie.frames => {
+                Some(movie::frame_hash(&self.display) == movie.final_hash)
+            }
+            _ => None,
+        }
+    }
+
+    // Passes an input event to the keypad, recording it when a movie is
+    // being recorded. The player's inputs are dropped during a replay.
+    pub fn handle_input(&mut self, event: InputEvent) {
+        if let (Some(input), Some(movie)) = (MovieInput::from_event(event), self.movie.as_mut()) {
+            if movie.is_replaying() {
+                return;
+            }
+            movie.record(self.frame_count, input);
+        }
+        if !self.keypad.handle_event(event, &mut self.config) {
+            self.keep_running = false;
         }
+    }
+
+    // Runs a frame with the inputs given to handle_input, or the movie's
+    // during a replay. For hosts that don't use run.
+    pub fn next_frame(&mut self) -> Result<FrameOutput, ChipError> {
+        self.apply_movie_inputs();
+        self.frame(None)
+    }
+
+    fn apply_movie_inputs(&mut self) {
+        let Some(movie) = self.movie.as_mut() else {
+            return;
+        };
+        for input in movie.inputs_for(self.frame_count) {
+            self.keypad.handle_event(input.event(), &mut self.config);
+        }
+    }
+
+    // Saves to the numbered slot of the ROM, returns the file written
+    pub fn save_to_slot(&self, slot: u8) -> Result<PathBuf, ChipError> {
+        let path = self.slot_path(slot)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(ChipError::SaveSlot)?;
+        }
+        fs::write(&path, self.save_state()).map_err(ChipError::SaveSlot)?;
+        Ok(path)
+    }
+
+    pub fn load_from_slot(&mut self, slot: u8) -> Result<(), ChipError> {
+        let bytes = fs::read(self.slot_path(slot)?).map_err(ChipError::SaveSlot)?;
+        self.load_state(&bytes)
+    }
+
+    fn slot_path(&self, slot: u8) -> Result<PathBuf, ChipError> {
+        self.rom_hash
+            .as_deref()
+            .and_then(|hash| savestate::slot_path(hash, slot))
+            .ok_or(ChipError::NoSaveSlots)
+    }
+
+    // Writes a line for each instruction that passes the tracer's filter
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    pub fn cycle_count(&self) -> u64 {
+        self.cycle_count
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
 
-        self.ram[Chip::PROGRAM_START..(Chip::PROGRAM_START + buffer.len())]
-            .copy_from_slice(&buffer);
+    // Watchpoints hit by the last instruction
+    pub fn watch_hits(&self) -> &[WatchHit] {
+        &self.watch_hits
+    }
+
+    pub fn opcode_at(&self, address: usize) -> Option<u16> {
+        let memory = self.memory();
+        Some(u16::from_be_bytes([
+            *memory.get(address)?,
+            *memory.get(address + 1)?,
+        ]))
+    }
 
-        self.config.adjust_to_rom(&buffer);
+    // None when the ROM isn't in the program database
+    pub fn metadata(&self) -> Option<&RomMetadata> {
+        self.metadata.as_ref()
+    }
 
-        Ok(String::from("ROM Loaded on memory"))
+    // Only set when the platform had to be guessed
+    pub fn detection(&self) -> Option<&Detection> {
+        self.detection.as_ref()
     }
 
-    pub fn run(&mut self) -> Result<(), io::Error> {
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
+
+    pub fn run(
+        &mut self,
+        video: &mut impl Video,
+        input: &mut impl Input,
+        audio: &mut impl Audio,
+    ) -> Result<(), ChipError> {
+        self.run_with_debugger(video, input, audio, &mut Debugger::new())
+    }
+
+    // Like run, the pause hotkey and breakpoints stop the emulator and the
+    // debugger takes commands from the terminal until it's resumed
+    pub fn run_with_debugger(
+        &mut self,
+        video: &mut impl Video,
+        input: &mut impl Input,
+        audio: &mut impl Audio,
+        debugger: &mut Debugger,
+    ) -> Result<(), ChipError> {
+        let frame_duration = Duration::from_nanos(1_000_000_000 / Config::TIMER_HZ);
+        let mut next_frame = Instant::now();
+        let mut slot = 0;
+        let mut rewind = Rewind::new(Rewind::DEFAULT_FRAMES);
+        let mut rewinding = false;
+
+        if debugger.is_paused() {
+            println!("{}", debugger.status(self));
+            debugger::print_prompt();
+        }
+
         while self.keep_running {
-            if !self.waiting_key {
-                self.keep_running = self.keypad.handle_events(&mut self.config);
+            while let Some(event) = input.poll_event() {
+                match event {
+                    InputEvent::Pause if !debugger.is_paused() => {
+                        debugger.pause();
+                        println!("\nPaused\n{}", debugger.status(self));
+                        debugger::print_prompt();
+                    }
+                    InputEvent::SaveState => match self.save_to_slot(slot) {
+                        Ok(path) => println!("Saved slot {} to {}", slot, path.display()),
+                        Err(e) => println!("Couldn't save slot {}: {}", slot, e),
+                    },
+                    InputEvent::LoadState | InputEvent::Rewind(true) if self.movie.is_some() => {
+                        println!("Loading states and rewinding would break the movie");
+                    }
+                    InputEvent::LoadState => match self.load_from_slot(slot) {
+                        Ok(()) => println!("Loaded slot {}", slot),
+                        Err(e) => println!("Couldn't load slot {}: {}", slot, e),
+                    },
+                    InputEvent::ChangeSlot(delta) => {
+                        slot = (slot as i32 + delta).rem_euclid(savestate::SLOTS as i32) as u8;
+                        println!("Save slot {}", slot);
+                    }
+                    InputEvent::Rewind(held) => rewinding = held,
+                    _ => self.handle_input(event),
+                }
             }
 
-            if self.sound_reg > 0 {
-                self.speaker.start();
-                self.sound_reg -= 1;
+            if debugger.is_paused() {
+                debugger.poll_stdin(self)?;
+            }
+
+            // one frame back per frame while the rewind key is held
+            let sound = if rewinding && !debugger.is_paused() {
+                if let Some(state) = rewind.pop() {
+                    self.load_state(&state)?;
+                }
+                self.display.set_buzzer(false);
+                false
             } else {
-                self.speaker.stop();
+                let frame_count = self.frame_count;
+                if !debugger.is_paused() {
+                    self.apply_movie_inputs();
+                }
+                let sound = self.frame(Some(debugger))?.sound;
+                if self.frame_count != frame_count {
+                    rewind.push(self.save_state());
+                }
+                sound
+            };
+
+            if sound {
+                audio.set_pattern(self.audio_pattern.as_ref(), self.pitch);
+                audio.start();
+            } else {
+                audio.stop();
+            }
+
+            video.render(&self.display);
+
+            if self.replay_result().is_some() {
+                self.keep_running = false;
             }
 
-            if self.delay_reg > 0 {
-                self.delay_reg -= 1;
+            // Deadlines are absolute so the time spent emulating and rendering
+            // doesn't add up as drift. If we fall far behind (window dragged,
+            // process suspended) start over instead of rushing to catch up.
+            next_frame += frame_duration;
+            let now = Instant::now();
+            if next_frame > now {
+                sleep(next_frame - now);
+            } else if now - next_frame > frame_duration * 5 {
+                next_frame = now;
             }
+        }
+        Ok(())
+    }
+
+    // Runs a single frame with the given keypad state and returns the result.
+    // Lets a host drive the emulator at its own pace instead of using run.
+    // Key k is pressed when bit (15 - k) of keys is set, as in Keypad.
+    pub fn step_frame(&mut self, keys: u16) -> Result<FrameOutput, ChipError> {
+        self.keypad.set_keys(keys);
+        self.frame(None)
+    }
+
+    // Nothing runs, not even the timers, while the debugger is paused
+    fn frame(&mut self, mut debugger: Option<&mut Debugger>) -> Result<FrameOutput, ChipError> {
+        if debugger
+            .as_ref()
+            .is_some_and(|debugger| debugger.is_paused())
+        {
+            return Ok(self.frame_output(false, 0));
+        }
+
+        let sound = self.sound_reg > 0;
+        if sound {
+            self.sound_reg -= 1;
+        }
+        self.display.set_buzzer(sound);
+
+        if self.delay_reg > 0 {
+            self.delay_reg -= 1;
+        }
+
+        let cycles = self.cycle_remainder + self.config.ips;
+        self.cycle_remainder = cycles % Config::TIMER_HZ;
 
-            for _ in 0..self.config.ipf {
-                self.update();
-                if self.config.vblank && self.drew_on_frame {
+        let mut instructions = 0;
+        for _ in 0..cycles / Config::TIMER_HZ {
+            if !self.keep_running {
+                break;
+            }
+            if let Some(debugger) = debugger.as_deref_mut() {
+                if let Some(reason) = debugger.check(self) {
+                    debugger.report_pause(self, &reason);
+                    break;
+                }
+            }
+            self.update()?;
+            instructions += 1;
+            if let Some(debugger) = debugger.as_deref_mut() {
+                if let Some(hits) = debugger.check_watchpoints(self) {
+                    debugger.report_pause(self, &format!("watchpoint hit\n{}", hits));
                     break;
                 }
             }
+            if self.waiting_key || (self.config.vblank && self.drew_on_frame) {
+                break;
+            }
+        }
 
-            self.drew_on_frame = false;
+        self.drew_on_frame = false;
+        self.frame_count += 1;
 
-            self.display.render();
-            sleep(Duration::from_millis(1000 / self.config.rate));
+        Ok(self.frame_output(sound, instructions))
+    }
+
+    fn frame_output(&self, sound: bool, instructions: u64) -> FrameOutput {
+        FrameOutput {
+            width: self.display.width(),
+            height: self.display.height(),
+            planes: [
+                self.display.plane(0).to_vec(),
+                self.display.plane(1).to_vec(),
+            ],
+            sound,
+            instructions,
         }
-        Ok(())
     }
 
-    fn fetch_and_decode(&mut self) -> Instruction {
-        let raw_instruction =
-            ((self.ram[self.pc_reg] as u16) << 8) | (self.ram[self.pc_reg + 1] as u16);
+    fn fetch_and_decode(&mut self) -> Result<Instruction, ChipError> {
+        let memory_size = self.memory_size();
+        if self.pc_reg + 1 >= memory_size {
+            return Err(ChipError::MemoryOutOfBounds {
+                pc: self.pc_reg as u16,
+                address: self.pc_reg.max(memory_size),
+            });
+        }
+
+        let raw_instruction = u16::from_be_bytes([
+            self.access_mem(self.pc_reg, MemoryAccess::Fetch)?,
+            self.access_mem(self.pc_reg + 1, MemoryAccess::Fetch)?,
+        ]);
 
         self.pc_reg += 2;
-        Instruction::new(raw_instruction)
+        Ok(Instruction::new(raw_instruction))
+    }
+
+    // Address of the instruction being executed, pc_reg already points to the next one
+    fn instruction_pc(&self) -> u16 {
+        self.pc_reg.wrapping_sub(2) as u16
+    }
+
+    fn read_mem(&mut self, address: usize) -> Result<u8, ChipError> {
+        self.access_mem(address, MemoryAccess::Read)
+    }
+
+    fn write_mem(&mut self, address: usize, value: u8) -> Result<(), ChipError> {
+        self.access_mem(address, MemoryAccess::Write(value))
+            .map(|_| ())
+    }
+
+    // Every access the program makes to memory goes through here, so the
+    // watchpoints see all of them. Returns the byte before the access.
+    fn access_mem(&mut self, address: usize, access: MemoryAccess) -> Result<u8, ChipError> {
+        if address >= self.memory_size() {
+            return Err(ChipError::MemoryOutOfBounds {
+                pc: self.instruction_pc(),
+                address,
+            });
+        }
+
+        let old = self.ram[address];
+        let (write, new) = match access {
+            MemoryAccess::Fetch => return Ok(old),
+            MemoryAccess::Read => (false, old),
+            MemoryAccess::Write(value) => (true, value),
+        };
+        self.ram[address] = new;
+
+        if self
+            .watchpoints
+            .iter()
+            .any(|w| w.watches_memory(address as u16, write))
+        {
+            self.watch_hits.push(WatchHit {
+                location: Location::Memory(address as u16),
+                pc: self.instruction_pc(),
+                write,
+                old: old as u16,
+                new: new as u16,
+            });
+        }
+        Ok(old)
+    }
+
+    // Skips the next instruction. On XO-CHIP F000 nnnn is 4 bytes long.
+    fn skip_next(&mut self) {
+        let long_load = self.config.instruction_set.has_xochip()
+            && self.access_mem(self.pc_reg, MemoryAccess::Fetch).ok() == Some(0xF0)
+            && self.access_mem(self.pc_reg + 1, MemoryAccess::Fetch).ok() == Some(0x00);
+
+        self.pc_reg += if long_load { 4 } else { 2 };
+    }
+
+    pub fn update(&mut self) -> Result<(), ChipError> {
+        self.watch_hits.clear();
+        let pc = self.pc_reg as u16;
+        let before = (!self.watchpoints.is_empty()).then_some((self.regs, self.i_reg));
+
+        let instruction = self.fetch_and_decode()?;
+        if self
+            .tracer
+            .as_ref()
+            .is_some_and(|tracer| tracer.filter().matches(pc, self.frame_count))
+        {
+            let line = trace::line(self, self.cycle_count, pc, instruction.raw);
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.write_line(&line).map_err(ChipError::Trace)?;
+            }
+        }
+        self.cycle_count += 1;
+        self.execute(instruction)?;
+
+        if let Some((regs, i_reg)) = before {
+            self.check_register_watchpoints(pc, regs, i_reg);
+        }
+        Ok(())
+    }
+
+    // Registers are compared with their values before the instruction ran
+    fn check_register_watchpoints(
+        &mut self,
+        pc: u16,
+        regs: [u8; Chip::GENERAL_REGISTERS],
+        i_reg: usize,
+    ) {
+        for watchpoint in &self.watchpoints {
+            let (location, old, new) = match *watchpoint {
+                Watchpoint::Register(x) => (
+                    Location::Register(x),
+                    regs[x as usize] as u16,
+                    self.regs[x as usize] as u16,
+                ),
+                Watchpoint::I => (Location::I, i_reg as u16, self.i_reg as u16),
+                Watchpoint::Memory { .. } => continue,
+            };
+            if old != new {
+                self.watch_hits.push(WatchHit {
+                    location,
+                    pc,
+                    write: true,
+                    old,
+                    new,
+                });
+            }
+        }
     }
 
-    pub fn update(&mut self) {
-        let instruction = self.fetch_and_decode();
+    fn execute(&mut self, instruction: Instruction) -> Result<(), ChipError> {
+        let schip = self.config.instruction_set.has_superchip();
+        let xo = self.config.instruction_set.has_xochip();
+        let c8x = self.config.instruction_set.has_chip8x();
 
         match instruction.parts {
+            (0x0, 0x0, 0xC, _) if schip => self.scroll_down(instruction.n),
+            (0x0, 0x0, 0xD, _) if xo => self.scroll_up(instruction.n),
             (0x0, 0x0, 0xE, 0x0) => self.cls(),
-            (0x0, 0x0, 0xE, 0xE) => self.ret(),
+            (0x0, 0x0, 0xE, 0xE) => self.ret()?,
+            (0x0, 0x2, 0xA, 0x0) if c8x => self.cycle_background(),
+            (0x0, 0x0, 0xF, 0xB) if schip => self.scroll_right(),
+            (0x0, 0x0, 0xF, 0xC) if schip => self.scroll_left(),
+            (0x0, 0x0, 0xF, 0xD) if schip => self.exit(),
+            (0x0, 0x0, 0xF, 0xE) if schip => self.set_hires(false),
+            (0x0, 0x0, 0xF, 0xF) if schip && self.config.hires => self.set_hires(true),
             (0x1, _, _, _) => self.jump(instruction.nnn),
-            (0x2, _, _, _) => self.call(instruction.nnn),
+            (0x2, _, _, _) => self.call(instruction.nnn)?,
             (0x3, _, _, _) => self.skip_if_equal_byte(instruction.x, instruction.kk),
             (0x4, _, _, _) => self.skip_if_not_equal_byte(instruction.x, instruction.kk),
+            (0x5, _, _, 0x1) if c8x => self.add_nibbles(instruction.x, instruction.y),
+            (0x5, _, _, 0x2) if xo => self.write_range_to_mem(instruction.x, instruction.y)?,
+            (0x5, _, _, 0x3) if xo => self.read_range_from_mem(instruction.x, instruction.y)?,
             (0x5, _, _, _) => self.skip_if_equal_registers(instruction.x, instruction.y),
             (0x6, _, _, _) => self.load_byte_to_reg(instruction.x, instruction.kk),
             (0x7, _, _, _) => self.add_byte_to_reg(instruction.x, instruction.kk),
@@ -240,21 +1072,50 @@ impl Chip {
             (0x8, _, _, 0xE) => self.shift_left(instruction.x, instruction.y),
             (0x9, _, _, 0x0) => self.skip_if_not_equal_registers(instruction.x, instruction.y),
             (0xA, _, _, _) => self.load_to_i_reg(instruction.nnn),
+            (0xB, _, _, 0x0) if c8x => self.color_zone(instruction.x, instruction.y),
+            (0xB, _, _, _) if c8x => self.color_rows(instruction.x, instruction.y, instruction.n),
             (0xB, _, _, _) => self.jump_with_offset(instruction.x, instruction.nnn),
             (0xC, _, _, _) => self.rand(instruction.x, instruction.kk),
-            (0xD, _, _, _) => self.draw(instruction.x, instruction.y, instruction.n),
+            (0xD, _, _, _) => self.draw(instruction.x, instruction.y, instruction.n)?,
             (0xE, _, 0x9, 0xE) => self.skip_if_key(instruction.x),
             (0xE, _, 0xA, 0x1) => self.skip_if_not_key(instruction.x),
+            (0xE, _, 0xF, 0x2) if c8x => self.skip_if_second_key(instruction.x),
+            (0xE, _, 0xF, 0x5) if c8x => self.skip_if_not_second_key(instruction.x),
+            (0xF, 0x0, 0x0, 0x0) if xo => self.long_load_to_i_reg()?,
+            (0xF, _, 0x0, 0x1) if xo => self.select_planes(instruction.x),
+            (0xF, 0x0, 0x0, 0x2) if xo => self.load_audio_pattern()?,
             (0xF, _, 0x0, 0x7) => self.set_reg_from_delay_timer(instruction.x),
             (0xF, _, 0x0, 0xA) => self.wait_key(instruction.x),
             (0xF, _, 0x1, 0x5) => self.set_delay_timer_from_reg(instruction.x),
             (0xF, _, 0x1, 0x8) => self.set_sound_timer_from_reg(instruction.x),
             (0xF, _, 0x1, 0xE) => self.add_to_i(instruction.x),
             (0xF, _, 0x2, 0x9) => self.i_to_digit_sprite(instruction.x),
-            (0xF, _, 0x3, 0x3) => self.decimal_reg_to_memory(instruction.x),
-            (0xF, _, 0x5, 0x5) => self.write_regs_to_mem(instruction.x),
-            (0xF, _, 0x6, 0x5) => self.read_regs_from_mem(instruction.x),
-            _ => {}
+            (0xF, _, 0x3, 0x0) if schip => self.i_to_big_digit_sprite(instruction.x),
+            (0xF, _, 0x3, 0xA) if xo => self.set_pitch(instruction.x),
+            (0xF, _, 0x3, 0x3) => self.decimal_reg_to_memory(instruction.x)?,
+            (0xF, _, 0x5, 0x5) => self.write_regs_to_mem(instruction.x)?,
+            (0xF, _, 0x6, 0x5) => self.read_regs_from_mem(instruction.x)?,
+            (0xF, _, 0x7, 0x5) if schip => self.save_rpl_flags(instruction.x),
+            (0xF, _, 0x8, 0x5) if schip => self.load_rpl_flags(instruction.x),
+            (0xF, _, 0xF, 0x8) if c8x => self.output_to_port(instruction.x),
+            (0xF, _, 0xF, 0xB) if c8x => self.input_from_port(instruction.x),
+            _ => self.unknown_opcode(instruction.raw)?,
+        }
+
+        Ok(())
+    }
+
+    fn unknown_opcode(&mut self, opcode: u16) -> Result<(), ChipError> {
+        self.unknown_opcodes += 1;
+        let pc = self.instruction_pc();
+
+        match self.config.unknown_opcode {
+            OpcodePolicy::Ignore => Ok(()),
+            OpcodePolicy::Log => {
+                eprintln!("Unknown opcode {:04X} at {:04X}", opcode, pc);
+                Ok(())
+            }
+            OpcodePolicy::Halt => Err(ChipError::InvalidOpcode { pc, opcode }),
         }
     }
 
@@ -262,19 +1123,72 @@ impl Chip {
     // Jump to a machine code routine at nnn.
     // This instruction is only used on the old computers on which Chip-8 was originally implemented. It is ignored by modern interpreters.
 
+    // 00Cn - SCD nibble (SUPER-CHIP)
+    // Scroll the display down by n pixels.
+    fn scroll_down(&mut self, n: u8) {
+        self.display.scroll_down(n as usize);
+    }
+
+    // 00Dn - SCU nibble (XO-CHIP)
+    // Scroll the selected planes up by n pixels.
+    fn scroll_up(&mut self, n: u8) {
+        self.display.scroll_up(n as usize);
+    }
+
     // 00E0 - CLS
     // Clear the display.
     fn cls(&mut self) {
         self.display.clear();
-        self.display.render();
     }
 
     // 00EE - RET
     // Return from a subroutine.
     // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<(), ChipError> {
+        if self.sp_reg == 0 {
+            return Err(ChipError::StackUnderflow {
+                pc: self.instruction_pc(),
+            });
+        }
+
         self.sp_reg -= 1;
-        self.pc_reg = self.stack[self.sp_reg as usize] as usize;
+        self.pc_reg = self.stack[self.sp_reg] as usize;
+        Ok(())
+    }
+
+    // 00FB - SCR (SUPER-CHIP)
+    // Scroll the display right by 4 pixels.
+    fn scroll_right(&mut self) {
+        self.display.scroll_right(4);
+    }
+
+    // 00FC - SCL (SUPER-CHIP)
+    // Scroll the display left by 4 pixels.
+    fn scroll_left(&mut self) {
+        self.display.scroll_left(4);
+    }
+
+    // 00FD - EXIT (SUPER-CHIP)
+    // Exit the interpreter.
+    fn exit(&mut self) {
+        self.keep_running = false;
+    }
+
+    // 00FE - LOW (SUPER-CHIP)
+    // Switch to the 64x32 lores mode.
+    // 00FF - HIGH (SUPER-CHIP)
+    // Switch to the 128x64 hires mode.
+    // The display is cleared when switching.
+    fn set_hires(&mut self, hires: bool) {
+        self.display.set_hires(hires);
+    }
+
+    // 02A0 - BGCOL (CHIP-8X)
+    // Step the background colour through blue, black, green and red.
+    fn cycle_background(&mut self) {
+        if let Some(layer) = self.display.color_layer_mut() {
+            layer.next_background();
+        }
     }
 
     // 1nnn - JP addr
@@ -287,10 +1201,17 @@ impl Chip {
     // 2nnn - CALL addr
     // Call subroutine at nnn.
     // The interpreter increments the stack pointer, then puts the current PC on the top of the stack. The PC is then set to nnn.
-    fn call(&mut self, addr: u16) {
-        self.stack[self.sp_reg as usize] = self.pc_reg as u16;
+    fn call(&mut self, addr: u16) -> Result<(), ChipError> {
+        if self.sp_reg == Chip::STACK_SIZE {
+            return Err(ChipError::StackOverflow {
+                pc: self.instruction_pc(),
+            });
+        }
+
+        self.stack[self.sp_reg] = self.pc_reg as u16;
         self.sp_reg += 1;
         self.pc_reg = addr as usize;
+        Ok(())
     }
 
     // 3xkk - SE Vx, byte
@@ -298,7 +1219,7 @@ impl Chip {
     // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
     fn skip_if_equal_byte(&mut self, x: u8, kk: u8) {
         if self.regs[x as usize] == kk {
-            self.pc_reg += 2;
+            self.skip_next();
         }
     }
 
@@ -307,7 +1228,7 @@ impl Chip {
     // The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
     fn skip_if_not_equal_byte(&mut self, x: u8, kk: u8) {
         if self.regs[x as usize] != kk {
-            self.pc_reg += 2;
+            self.skip_next();
         }
     }
 
@@ -316,7 +1237,44 @@ impl Chip {
     // The interpreter compares register Vx to register Vy, and if they are equal, increments the program counter by 2.
     fn skip_if_equal_registers(&mut self, x: u8, y: u8) {
         if self.regs[x as usize] == self.regs[y as usize] {
-            self.pc_reg += 2;
+            self.skip_next();
+        }
+    }
+
+    // 5xy1 - ADD Vx, Vy (CHIP-8X)
+    // Set Vx = Vx + Vy, adding each nibble separately, modulo 8. VF is not affected.
+    fn add_nibbles(&mut self, x: u8, y: u8) {
+        let (vx, vy) = (self.regs[x as usize], self.regs[y as usize]);
+        let high = ((vx >> 4) + (vy >> 4)) & 0x7;
+        let low = ((vx & 0xF) + (vy & 0xF)) & 0x7;
+        self.regs[x as usize] = (high << 4) | low;
+    }
+
+    // 5xy2 - SAVE Vx - Vy (XO-CHIP)
+    // Store registers Vx through Vy in memory starting at location I, in reverse order if x > y.
+    // I is not modified.
+    fn write_range_to_mem(&mut self, x: u8, y: u8) -> Result<(), ChipError> {
+        for (offset, reg) in Chip::register_range(x, y).enumerate() {
+            self.write_mem(self.i_reg + offset, self.regs[reg])?;
+        }
+        Ok(())
+    }
+
+    // 5xy3 - LOAD Vx - Vy (XO-CHIP)
+    // Read registers Vx through Vy from memory starting at location I, in reverse order if x > y.
+    // I is not modified.
+    fn read_range_from_mem(&mut self, x: u8, y: u8) -> Result<(), ChipError> {
+        for (offset, reg) in Chip::register_range(x, y).enumerate() {
+            self.regs[reg] = self.read_mem(self.i_reg + offset)?;
+        }
+        Ok(())
+    }
+
+    fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x as usize..=y as usize)
+        } else {
+            Box::new((y as usize..=x as usize).rev())
         }
     }
 
@@ -423,7 +1381,7 @@ impl Chip {
     // The values of Vx and Vy are compared, and if they are not equal, the program counter is increased by 2.
     fn skip_if_not_equal_registers(&mut self, x: u8, y: u8) {
         if self.regs[x as usize] != self.regs[y as usize] {
-            self.pc_reg += 2;
+            self.skip_next();
         }
     }
 
@@ -442,37 +1400,108 @@ impl Chip {
             addr as usize + self.regs[if self.config.jump { x } else { 0 } as usize] as usize;
     }
 
+    // Bxy0 - COL Vx, Vy (CHIP-8X)
+    // Set the foreground colour of a zone to Vy. Zones are 8x4 pixels. The low nibble of Vx is the
+    // left zone and the high nibble the width minus one, V(x+1) holds the top zone and height the same way.
+    fn color_zone(&mut self, x: u8, y: u8) {
+        let horizontal = self.regs[x as usize];
+        let vertical = self.regs[(x as usize + 1) % Chip::GENERAL_REGISTERS];
+        let color = self.regs[y as usize];
+
+        if let Some(layer) = self.display.color_layer_mut() {
+            let left = (horizontal & 0xF) as usize;
+            let top = (vertical & 0xF) as usize * 4;
+            for column in left..=left + (horizontal >> 4) as usize {
+                for row in top..top + ((vertical >> 4) as usize + 1) * 4 {
+                    layer.set_foreground(column, row, color);
+                }
+            }
+        }
+    }
+
+    // Bxyn - COL Vx, Vy, nibble (CHIP-8X)
+    // Set the foreground colour of n rows, starting at V(x+1), in the 8 pixel wide column holding Vx to Vy.
+    fn color_rows(&mut self, x: u8, y: u8, n: u8) {
+        let column = self.regs[x as usize] as usize / ColorLayer::CELL_WIDTH;
+        let top = self.regs[(x as usize + 1) % Chip::GENERAL_REGISTERS] as usize;
+        let color = self.regs[y as usize];
+
+        if let Some(layer) = self.display.color_layer_mut() {
+            for row in top..top + n as usize {
+                layer.set_foreground(column, row, color);
+            }
+        }
+    }
+
     // Cxkk - RND Vx, byte
     // Set Vx = random byte AND kk.
     // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx. See instruction 8xy2 for more information on AND.
     fn rand(&mut self, x: u8, kk: u8) {
-        self.regs[x as usize] = self.rng.gen::<u8>() & kk;
+        self.regs[x as usize] = self.rng.next_byte() & kk;
     }
 
     // Dxyn - DRW Vx, Vy, nibble
     // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
     // The interpreter reads n bytes from memory, starting at the address stored in I. These bytes are then displayed as sprites on screen at coordinates (Vx, Vy). Sprites are XORed onto the existing screen. If this causes any pixels to be erased, VF is set to 1, otherwise it is set to 0. If the sprite is positioned so part of it is outside the coordinates of the display, it wraps around to the opposite side of the screen. See instruction 8xy3 for more information on XOR, and section 2.4, Display, for more information on the Chip-8 screen and sprites.
-    fn draw(&mut self, x: u8, y: u8, n: u8) {
+    //
+    // Dxy0 - DRW Vx, Vy, 0 (SUPER-CHIP)
+    // Draws a 16x16 sprite, made of 32 bytes starting at I (two bytes per row).
+    // In hires mode VF is set to the number of rows that collided or were clipped by the bottom of the screen.
+    fn draw(&mut self, x: u8, y: u8, n: u8) -> Result<(), ChipError> {
         self.drew_on_frame = true;
-        let mut collision = 0;
+
+        let schip = self.config.instruction_set.has_superchip();
+        let (rows, width) = if n == 0 && schip {
+            (16, 16)
+        } else {
+            (n as usize, 8)
+        };
+        let count_rows =
+            self.config.instruction_set == InstructionSet::SuperChip && self.display.is_hires();
 
         // independent of wrap quirk on or off (this would do the trick to draw the sprite if it's entirely off the screen with wrap off)
-        let dx = self.regs[x as usize] as usize % Display::WIDTH;
-        let dy = self.regs[y as usize] as usize % Display::HEIGHT;
-
-        for i in 0..n {
-            if self.display.draw(
-                dx as u8,
-                dy as u8 + i,
-                self.ram[self.i_reg + i as usize],
-                self.config.wrap,
-            ) {
-                collision = 1;
+        let dx = self.regs[x as usize] as usize % self.display.width();
+        let dy = self.regs[y as usize] as usize % self.display.height();
+
+        // with several planes selected, the sprite data for each plane follows the previous one
+        let mut address = self.i_reg;
+        let mut collisions = 0;
+        for plane in 0..Display::PLANES {
+            if !self.display.is_plane_selected(plane) {
+                continue;
+            }
+
+            for i in 0..rows {
+                let sprite = if width == 16 {
+                    address += 2;
+                    u16::from_be_bytes([self.read_mem(address - 2)?, self.read_mem(address - 1)?])
+                } else {
+                    address += 1;
+                    self.read_mem(address - 1)? as u16
+                };
+
+                if !self.config.wrap && dy + i >= self.display.height() {
+                    if count_rows {
+                        collisions += 1;
+                    }
+                    continue;
+                }
+
+                if self
+                    .display
+                    .draw(plane, dx, dy + i, sprite, width, self.config.wrap)
+                {
+                    collisions += 1;
+                }
             }
         }
 
-        self.regs[0xF] = collision;
-        self.display.render();
+        self.regs[0xF] = if count_rows {
+            collisions
+        } else {
+            collisions.min(1)
+        };
+        Ok(())
     }
 
     // Ex9E - SKP Vx
@@ -480,7 +1509,7 @@ impl Chip {
     // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the down position, PC is increased by 2.
     fn skip_if_key(&mut self, x: u8) {
         if self.keypad.is_pressed(self.regs[x as usize]) {
-            self.pc_reg += 2;
+            self.skip_next();
         }
     }
 
@@ -489,7 +1518,51 @@ impl Chip {
     // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position, PC is increased by 2.
     fn skip_if_not_key(&mut self, x: u8) {
         if !self.keypad.is_pressed(self.regs[x as usize]) {
-            self.pc_reg += 2;
+            self.skip_next();
+        }
+    }
+
+    // F000 nnnn - LD I, long addr (XO-CHIP)
+    // Set I = nnnn, the 16-bit word following the instruction.
+    fn long_load_to_i_reg(&mut self) -> Result<(), ChipError> {
+        // the operand is part of the instruction, read watchpoints ignore it
+        let high = self.access_mem(self.pc_reg, MemoryAccess::Fetch)?;
+        let low = self.access_mem(self.pc_reg + 1, MemoryAccess::Fetch)?;
+        self.i_reg = u16::from_be_bytes([high, low]) as usize;
+        self.pc_reg += 2;
+        Ok(())
+    }
+
+    // Fn01 - PLANE n (XO-CHIP)
+    // Select the bitplanes affected by drawing, clearing and scrolling. n is a bitmask, 0 to 3.
+    fn select_planes(&mut self, n: u8) {
+        self.display.select_planes(n);
+    }
+
+    // F002 - AUDIO (XO-CHIP)
+    // Load the 16 bytes at I into the audio pattern buffer.
+    fn load_audio_pattern(&mut self) -> Result<(), ChipError> {
+        let mut pattern = [0; 16];
+        for (i, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.read_mem(self.i_reg + i)?;
+        }
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+
+    // ExF2 - SKP2 Vx (CHIP-8X)
+    // Skip next instruction if key Vx of the second keypad is pressed.
+    fn skip_if_second_key(&mut self, x: u8) {
+        if self.keypad.is_second_pressed(self.regs[x as usize]) {
+            self.skip_next();
+        }
+    }
+
+    // ExF5 - SKNP2 Vx (CHIP-8X)
+    // Skip next instruction if key Vx of the second keypad is not pressed.
+    fn skip_if_not_second_key(&mut self, x: u8) {
+        if !self.keypad.is_second_pressed(self.regs[x as usize]) {
+            self.skip_next();
         }
     }
 
@@ -504,12 +1577,13 @@ impl Chip {
     // Wait for a key press, store the value of the key in Vx.
     // All execution stops until a key is pressed, then the value of that key is stored in Vx.
     fn wait_key(&mut self, x: u8) {
-        self.waiting_key = true;
-        if let Some(key) = self
-            .keypad
-            .block_read(&mut self.keep_running, &mut self.config)
-        {
-            self.regs[x as usize] = key as u8;
+        if !self.waiting_key {
+            self.waiting_key = true;
+            self.keypad.start_waiting();
+        }
+
+        if let Some(key) = self.keypad.take_released() {
+            self.regs[x as usize] = key;
             self.waiting_key = false;
         } else {
             self.pc_reg -= 2;
@@ -533,8 +1607,9 @@ impl Chip {
     // Fx1E - ADD I, Vx
     // Set I = I + Vx.
     // The values of I and Vx are added, and the results are stored in I.
+    // I is a 16-bit register, so the sum wraps past 0xFFFF.
     fn add_to_i(&mut self, x: u8) {
-        self.i_reg += self.regs[x as usize] as usize;
+        self.i_reg = (self.i_reg + self.regs[x as usize] as usize) & 0xFFFF;
     }
 
     // Fx29 - LD F, Vx
@@ -544,50 +1619,94 @@ impl Chip {
         self.i_reg = Chip::BYTES_PER_SPRITE as usize * self.regs[x as usize] as usize;
     }
 
+    // Fx30 - LD HF, Vx (SUPER-CHIP)
+    // Set I = location of the 8x10 sprite for digit Vx.
+    fn i_to_big_digit_sprite(&mut self, x: u8) {
+        self.i_reg = Chip::BIG_FONT_START
+            + Chip::BYTES_PER_BIG_SPRITE as usize * (self.regs[x as usize] & 0xF) as usize;
+    }
+
+    // Fx3A - PITCH Vx (XO-CHIP)
+    // Set the audio pattern playback rate to 4000 * 2 ^ ((Vx - 64) / 48) Hz.
+    fn set_pitch(&mut self, x: u8) {
+        self.pitch = self.regs[x as usize];
+    }
+
     // Fx33 - LD B, Vx
     // Store BCD representation of Vx in memory locations I, I+1, and I+2.
     // The interpreter takes the decimal value of Vx, and places the hundreds digit in memory at location in I, the tens digit at location I+1, and the ones digit at location I+2.
-    fn decimal_reg_to_memory(&mut self, x: u8) {
+    fn decimal_reg_to_memory(&mut self, x: u8) -> Result<(), ChipError> {
         let value = self.regs[x as usize];
-        self.ram[self.i_reg] = value / 100;
-        self.ram[self.i_reg + 1] = (value / 10) % 10;
-        self.ram[self.i_reg + 2] = value % 10;
+        self.write_mem(self.i_reg, value / 100)?;
+        self.write_mem(self.i_reg + 1, (value / 10) % 10)?;
+        self.write_mem(self.i_reg + 2, value % 10)
     }
 
     // Fx55 - LD [I], Vx
     // Store registers V0 through Vx in memory starting at location I.
     // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
-    fn write_regs_to_mem(&mut self, x: u8) {
+    fn write_regs_to_mem(&mut self, x: u8) -> Result<(), ChipError> {
         for i in 0..=x {
-            self.ram[self.i_reg + i as usize] = self.regs[i as usize]
+            self.write_mem(self.i_reg + i as usize, self.regs[i as usize])?;
         }
 
         if self.config.memory_leave_i_unchanged {
-            return;
+            return Ok(());
         }
 
         self.i_reg += x as usize;
         if !self.config.memory_increment_by_x {
             self.i_reg += 1;
         }
+        Ok(())
     }
 
     // Fx65 - LD Vx, [I]
     // Read registers V0 through Vx from memory starting at location I.
     // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-    fn read_regs_from_mem(&mut self, x: u8) {
+    fn read_regs_from_mem(&mut self, x: u8) -> Result<(), ChipError> {
         for i in 0..=x {
-            self.regs[i as usize] = self.ram[self.i_reg + i as usize];
+            self.regs[i as usize] = self.read_mem(self.i_reg + i as usize)?;
         }
 
         if self.config.memory_leave_i_unchanged {
-            return;
+            return Ok(());
         }
 
         self.i_reg += x as usize;
         if !self.config.memory_increment_by_x {
             self.i_reg += 1;
         }
+        Ok(())
+    }
+
+    // Fx75 - LD R, Vx (SUPER-CHIP)
+    // Store registers V0 through Vx in the RPL user flags.
+    fn save_rpl_flags(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.rpl_flags[..count].copy_from_slice(&self.regs[..count]);
+    }
+
+    // Fx85 - LD Vx, R (SUPER-CHIP)
+    // Read registers V0 through Vx from the RPL user flags.
+    fn load_rpl_flags(&mut self, x: u8) {
+        let count = x as usize + 1;
+        self.regs[..count].copy_from_slice(&self.rpl_flags[..count]);
+    }
+
+    // FxF8 - OUT Vx (CHIP-8X)
+    // Write Vx to the I/O port.
+    fn output_to_port(&mut self, x: u8) {
+        self.io_output = self.regs[x as usize];
+    }
+
+    // FxFB - IN Vx (CHIP-8X)
+    // Wait for a value on the I/O port and store it in Vx.
+    fn input_from_port(&mut self, x: u8) {
+        match self.io_input.take() {
+            Some(value) => self.regs[x as usize] = value,
+            None => self.pc_reg -= 2,
+        }
     }
 }
 
@@ -598,18 +1717,7 @@ mod tests {
     use super::*;
 
     fn init_chip() -> Chip {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-        let audio_subsystem = sdl_context.audio().unwrap();
-        let window = video_subsystem
-            .window("Chip8 Emulator", 800, 600)
-            .position_centered()
-            .resizable()
-            .build()
-            .unwrap();
-        let canvas: WindowCanvas = window.into_canvas().build().unwrap();
-        let event_queue = sdl_context.event_pump().unwrap();
-        Chip::new(canvas, event_queue, audio_subsystem)
+        Chip::new()
     }
 
     #[test]
@@ -645,4 +1753,419 @@ mod tests {
         assert_eq!(chip.regs[0x0], 0x9);
         assert_eq!(chip.regs[0xF], 0);
     }
+
+    #[test]
+    fn step_frame() {
+        let mut chip = init_chip();
+        // LD V0, 0x05; LD F, V0; DRW V0, V0, 5; LD V1, 2; LD ST, V1; JP 0x20A
+        let rom = [
+            0x60, 0x05, 0xF0, 0x29, 0xD0, 0x05, 0x61, 0x02, 0xF1, 0x18, 0x12, 0x0A,
+        ];
+        chip.load_rom(&rom).unwrap();
+
+        let output = chip.step_frame(0).unwrap();
+        // vblank quirk stops the frame after the draw
+        assert_eq!(output.instructions, 3);
+        assert!(!output.sound);
+        assert_eq!(output.planes[0][5], 0xF0_u128 << 115);
+        assert_eq!(output.planes[0][9], 0xF0_u128 << 115);
+
+        let output = chip.step_frame(0).unwrap();
+        assert_eq!(output.instructions, chip.config.ips / Config::TIMER_HZ);
+        assert!(chip.step_frame(0).unwrap().sound);
+    }
+
+    #[test]
+    fn speed_independent_of_timers() {
+        let mut chip = init_chip();
+        // LD V0, 0x3C; LD DT, V0; JP 0x204
+        chip.load_rom(&[0x60, 0x3C, 0xF0, 0x15, 0x12, 0x04])
+            .unwrap();
+        chip.config.ips = 90;
+
+        let total: u64 = (0..4)
+            .map(|_| chip.step_frame(0).unwrap().instructions)
+            .sum();
+        assert_eq!(total, 6);
+
+        // one tick per frame regardless of speed
+        chip.config.ips = 6000;
+        for _ in 0..10 {
+            chip.step_frame(0).unwrap();
+        }
+        assert_eq!(chip.delay_reg, 0x3C - 12);
+    }
+
+    #[test]
+    fn seeded_rand() {
+        let mut config = Config::new();
+        config.seed = Some(1234);
+        let mut a = Chip::with_config(config);
+        let mut config = Config::new();
+        config.seed = Some(1234);
+        let mut b = Chip::with_config(config);
+
+        for _ in 0..16 {
+            a.rand(0x0, 0xFF);
+            b.rand(0x0, 0xFF);
+            assert_eq!(a.regs[0x0], b.regs[0x0]);
+        }
+    }
+
+    #[test]
+    fn superchip_hires_draw() {
+        let mut chip = init_chip();
+        chip.config.instruction_set = InstructionSet::SuperChip;
+        // HIGH; LD I, 0x300; DRW V0, V1, 0; DRW V0, V1, 0
+        chip.load_rom(&[0x00, 0xFF, 0xA3, 0x00, 0xD0, 0x10, 0xD0, 0x10])
+            .unwrap();
+        chip.ram[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        chip.regs[0x0] = 120;
+        chip.regs[0x1] = 60;
+
+        chip.update().unwrap();
+        assert_eq!(chip.display.width(), 128);
+        chip.update().unwrap();
+        chip.update().unwrap();
+        assert!(chip.display.is_pixel_on(127, 63));
+        // 12 rows clipped by the bottom of the screen
+        assert_eq!(chip.regs[0xF], 12);
+
+        chip.update().unwrap();
+        assert!(!chip.display.is_pixel_on(127, 63));
+        assert_eq!(chip.regs[0xF], 16);
+    }
+
+    #[test]
+    fn superchip_opcodes_disabled_on_chip8() {
+        let mut chip = init_chip();
+        chip.config.force_platform("originalChip8").unwrap();
+        chip.load_rom(&[0x00, 0xFF, 0x00, 0xFD]).unwrap();
+        assert!(chip.detection().is_none());
+        chip.update().unwrap();
+        chip.update().unwrap();
+        assert!(!chip.display.is_hires());
+        assert!(chip.is_running());
+        assert_eq!(chip.unknown_opcodes(), 2);
+    }
+
+    #[test]
+    fn hires_needs_the_resolution() {
+        let mut chip = init_chip();
+        chip.config.force_platform("superchip").unwrap();
+        chip.load_rom(&[0x00, 0xFF]).unwrap();
+        chip.config.hires = false;
+        chip.update().unwrap();
+        assert!(!chip.display.is_hires());
+        assert_eq!(chip.unknown_opcodes(), 1);
+    }
+
+    #[test]
+    fn detected_platform() {
+        let mut chip = init_chip();
+        chip.load_rom(&[0x00, 0xFF, 0x00, 0xFD]).unwrap();
+        assert_eq!(chip.detection().unwrap().platform, "superchip");
+        assert_eq!(chip.config.instruction_set, InstructionSet::SuperChip);
+        chip.update().unwrap();
+        assert!(chip.display.is_hires());
+    }
+
+    #[test]
+    fn detected_chip8x_is_scanned_from_0x300() {
+        // JP 0x304, then 02A0, only reached from 0x300
+        let mut chip = init_chip();
+        chip.load_rom_with_extension(&[0x13, 0x04, 0x00, 0xE0, 0x02, 0xA0], Some("c8x"))
+            .unwrap();
+        let detection = chip.detection().unwrap();
+        assert_eq!(detection.platform, "chip8x");
+        assert_eq!(detection.reasons[1], "02A0 (background) at 0x304");
+        assert_eq!(chip.pc_reg, 0x300);
+    }
+
+    #[test]
+    fn audio_pattern_is_reset() {
+        let mut chip = init_chip();
+        chip.config.force_platform("xochip").unwrap();
+        chip.load_rom(&[0x12, 0x00]).unwrap();
+        let state = chip.save_state();
+
+        chip.audio_pattern = Some([0xF0; 16]);
+        chip.pitch = 100;
+        chip.load_state(&state).unwrap();
+        assert_eq!(
+            (chip.audio_pattern, chip.pitch),
+            (None, Chip::DEFAULT_PITCH)
+        );
+
+        chip.audio_pattern = Some([0xF0; 16]);
+        chip.pitch = 100;
+        chip.load_rom(&[0x12, 0x00]).unwrap();
+        assert_eq!(
+            (chip.audio_pattern, chip.pitch),
+            (None, Chip::DEFAULT_PITCH)
+        );
+    }
+
+    #[test]
+    fn rpl_flags() {
+        let mut chip = init_chip();
+        chip.regs[..4].copy_from_slice(&[1, 2, 3, 4]);
+        chip.save_rpl_flags(0x2);
+        chip.regs = [0; Chip::GENERAL_REGISTERS];
+        chip.load_rpl_flags(0x3);
+        assert_eq!(chip.regs[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn xochip_memory_and_long_load() {
+        let mut chip = init_chip();
+        chip.config.instruction_set = InstructionSet::XoChip;
+        // LD I, 0xFFF0; SE V0, 0; LD I, 0x1234; LD [I], V0
+        chip.load_rom(&[
+            0xF0, 0x00, 0xFF, 0xF0, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0xF0, 0x55,
+        ])
+        .unwrap();
+
+        chip.update().unwrap();
+        assert_eq!(chip.i_reg, 0xFFF0);
+        // skipping a long load skips all 4 bytes
+        chip.update().unwrap();
+        assert_eq!(chip.pc_reg, 0x20A);
+
+        chip.regs[0x0] = 0xAB;
+        chip.update().unwrap();
+        assert_eq!(chip.ram[0xFFF0], 0xAB);
+    }
+
+    #[test]
+    fn xochip_register_ranges() {
+        let mut chip = init_chip();
+        chip.i_reg = 0x300;
+        chip.regs[..4].copy_from_slice(&[1, 2, 3, 4]);
+
+        chip.write_range_to_mem(0x3, 0x1).unwrap();
+        assert_eq!(chip.ram[0x300..0x303], [4, 3, 2]);
+        assert_eq!(chip.i_reg, 0x300);
+
+        chip.read_range_from_mem(0x5, 0x7).unwrap();
+        assert_eq!(chip.regs[0x5..0x8], [4, 3, 2]);
+    }
+
+    #[test]
+    fn xochip_planes_draw() {
+        let mut chip = init_chip();
+        chip.config.instruction_set = InstructionSet::XoChip;
+        chip.i_reg = 0x300;
+        chip.ram[0x300..0x302].copy_from_slice(&[0x80, 0x40]);
+
+        chip.select_planes(0x3);
+        chip.draw(0x0, 0x0, 1).unwrap();
+        assert_eq!(chip.display.color(0, 0), 0x1);
+        assert_eq!(chip.display.color(1, 0), 0x2);
+    }
+
+    #[test]
+    fn chip8x_colors() {
+        let mut chip = init_chip();
+        chip.config.instruction_set = InstructionSet::Chip8X;
+        chip.load_rom(&[0x02, 0xA0, 0xB0, 0x20, 0xB0, 0x22])
+            .unwrap();
+        assert_eq!(chip.pc_reg, 0x300);
+
+        chip.update().unwrap();
+        assert_eq!(chip.display.color_layer().unwrap().background(), 0);
+
+        // zone 1,0 with a width of 2 zones and a height of 1
+        chip.regs[0x0] = 0x11;
+        chip.regs[0x1] = 0x00;
+        chip.regs[0x2] = 0x4;
+        chip.update().unwrap();
+        let layer = chip.display.color_layer().unwrap();
+        assert_eq!(layer.foreground(8, 0), 0x4);
+        assert_eq!(layer.foreground(23, 3), 0x4);
+        assert_eq!(layer.foreground(24, 0), 0x1);
+        assert_eq!(layer.foreground(8, 4), 0x1);
+
+        // 2 rows at column 0x11 / 8, starting at row 5
+        chip.regs[0x1] = 5;
+        chip.update().unwrap();
+        let layer = chip.display.color_layer().unwrap();
+        assert_eq!(layer.foreground(16, 5), 0x4);
+        assert_eq!(layer.foreground(16, 6), 0x4);
+        assert_eq!(layer.foreground(16, 7), 0x1);
+    }
+
+    #[test]
+    fn keys_past_f_use_the_low_nibble() {
+        // SKP2 V0; SKP2 V0 (skipped); SKP V0; SKP V0 (skipped)
+        let mut chip = init_chip();
+        chip.config.instruction_set = InstructionSet::Chip8X;
+        chip.load_rom(&[0xE0, 0xF2, 0xE0, 0xF2, 0xE0, 0x9E, 0xE0, 0x9E])
+            .unwrap();
+        chip.regs[0x0] = 0x1F;
+        chip.handle_input(InputEvent::SecondKeyDown(0xF));
+        chip.update().unwrap();
+        assert_eq!(chip.pc_reg, 0x304);
+
+        chip.handle_input(InputEvent::KeyDown(0xF));
+        chip.update().unwrap();
+        assert_eq!(chip.pc_reg, 0x308);
+    }
+
+    #[test]
+    fn chip8x_add_nibbles() {
+        let mut chip = init_chip();
+        chip.regs[0x0] = 0x35;
+        chip.regs[0x1] = 0x64;
+        chip.add_nibbles(0x0, 0x1);
+        assert_eq!(chip.regs[0x0], 0x11);
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut chip = init_chip();
+        let rom = vec![0; 4096 - 512 + 1];
+        assert!(matches!(
+            chip.load_rom(&rom),
+            Err(ChipError::RomTooLarge { size: 3585, .. })
+        ));
+    }
+
+    #[test]
+    fn stack_errors() {
+        let mut chip = init_chip();
+        assert!(matches!(chip.ret(), Err(ChipError::StackUnderflow { .. })));
+
+        for _ in 0..16 {
+            chip.call(0x200).unwrap();
+        }
+        assert!(matches!(
+            chip.call(0x200),
+            Err(ChipError::StackOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn unknown_opcode_policy() {
+        let mut chip = init_chip();
+        chip.load_rom(&[0xFF, 0xFF, 0xFF, 0xFF]).unwrap();
+
+        chip.update().unwrap();
+        assert_eq!(chip.unknown_opcodes(), 1);
+
+        chip.config_mut().unknown_opcode = OpcodePolicy::Halt;
+        assert!(matches!(
+            chip.update(),
+            Err(ChipError::InvalidOpcode {
+                pc: 0x202,
+                opcode: 0xFFFF
+            })
+        ));
+        assert_eq!(chip.unknown_opcodes(), 2);
+    }
+
+    #[test]
+    fn save_and_load_state() {
+        let rom = include_bytes!("../roms/ibm.ch8");
+        let mut chip = Chip::new();
+        chip.load_rom(rom).unwrap();
+        chip.step_frame(0).unwrap();
+        let state = chip.save_state();
+
+        let later: Vec<FrameOutput> = (0..10).map(|_| chip.step_frame(0).unwrap()).collect();
+        let random = chip.rng.next_byte();
+        chip.load_state(&state).unwrap();
+        for frame in later {
+            assert_eq!(chip.step_frame(0).unwrap().planes, frame.planes);
+        }
+        assert_eq!(chip.rng.next_byte(), random);
+
+        // a fresh chip with another config takes everything from the state
+        let mut other = Chip::new();
+        other.load_rom(rom).unwrap();
+        other.config_mut().ips = 1;
+        other.load_state(&state).unwrap();
+        assert_eq!(other.config().ips, chip.config().ips);
+        assert_eq!(other.frame_count(), 1);
+        assert_eq!(other.save_state(), state);
+
+        let mut wrong_rom = Chip::new();
+        wrong_rom.load_rom(&[0x12, 0x00]).unwrap();
+        assert!(matches!(
+            wrong_rom.load_state(&state),
+            Err(ChipError::SaveState(SaveStateError::WrongRom { .. }))
+        ));
+    }
+
+    #[test]
+    fn truncated_state_changes_nothing() {
+        let rom = include_bytes!("../roms/ibm.ch8");
+        let mut chip = Chip::new();
+        chip.load_rom(rom).unwrap();
+        let hash = chip.rom_hash().unwrap().to_string();
+        let payload = savestate::decode(&chip.save_state(), &hash)
+            .unwrap()
+            .to_vec();
+
+        // cut off in the middle of the memory, after the config
+        chip.config_mut().ips = 1;
+        chip.step_frame(0).unwrap();
+        let before = chip.save_state();
+        let truncated = savestate::encode(&hash, &payload[..payload.len() / 2]);
+        assert!(matches!(
+            chip.load_state(&truncated),
+            Err(ChipError::SaveState(SaveStateError::Truncated))
+        ));
+        assert_eq!(chip.config().ips, 1);
+        assert_eq!(chip.save_state(), before);
+    }
+
+    #[test]
+    fn add_to_i_wraps() {
+        let mut chip = init_chip();
+        chip.i_reg = 0xFFF0;
+        chip.regs[0x3] = 0x20;
+        chip.add_to_i(0x3);
+        assert_eq!(chip.i_reg, 0x10);
+
+        chip.regs[0x3] = 0xFF;
+        for _ in 0..1000 {
+            chip.add_to_i(0x3);
+        }
+        assert_eq!(chip.i_reg, (0x10 + 1000 * 0xFF) & 0xFFFF);
+    }
+
+    #[test]
+    fn long_load_operand_is_fetched() {
+        let mut chip = init_chip();
+        chip.config.force_platform("xochip").unwrap();
+        chip.load_rom(&[0xF0, 0x00, 0x03, 0x00]).unwrap();
+        chip.add_watchpoint("200-203 r".parse().unwrap());
+        chip.update().unwrap();
+        assert_eq!(chip.i_reg, 0x300);
+        assert!(chip.watch_hits().is_empty());
+    }
+
+    #[test]
+    fn slots_need_a_rom() {
+        let chip = Chip::new();
+        let error = chip.save_to_slot(0).unwrap_err();
+        assert!(matches!(error, ChipError::NoSaveSlots));
+        assert!(error
+            .to_string()
+            .starts_with("Save slots need a loaded ROM"));
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        let mut chip = init_chip();
+        chip.i_reg = 0xFFE;
+        assert!(matches!(
+            chip.write_regs_to_mem(0x3),
+            Err(ChipError::MemoryOutOfBounds {
+                address: 0x1000,
+                ..
+            })
+        ));
+    }
 }
@@ -1,11 +1,72 @@
-use std::fs;
+use std::str::FromStr;
 
-use serde_json::Value;
-use sha1::Digest;
+use crate::database::RomMetadata;
+use crate::platform::{Platform, Quirks};
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
+// What to do when the interpreter finds an opcode it doesn't recognise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpcodePolicy {
+    Ignore,
+    Log,
+    Halt,
+}
+
+impl FromStr for OpcodePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(OpcodePolicy::Ignore),
+            "log" => Ok(OpcodePolicy::Log),
+            "halt" => Ok(OpcodePolicy::Halt),
+            _ => Err(format!(
+                "Unknown opcode policy '{}', expected ignore, log or halt",
+                s
+            )),
+        }
+    }
+}
+
+// Opcodes available on top of the original CHIP-8 set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionSet {
+    Chip8,
+    // SUPER-CHIP 1.1: hires mode, scrolling, big font and RPL flags
+    SuperChip,
+    // XO-CHIP: SUPER-CHIP plus 64K of memory, bitplanes and audio patterns
+    XoChip,
+    // CHIP-8X: colour display, second keypad and I/O port
+    Chip8X,
+}
+
+impl InstructionSet {
+    pub fn has_superchip(self) -> bool {
+        matches!(self, InstructionSet::SuperChip | InstructionSet::XoChip)
+    }
+
+    pub fn has_chip8x(self) -> bool {
+        self == InstructionSet::Chip8X
+    }
+
+    pub fn has_xochip(self) -> bool {
+        self == InstructionSet::XoChip
+    }
+}
+
+#[derive(Clone)]
 pub struct Config {
-    pub rate: u64,
-    pub ipf: u64,
+    // CPU speed, independent of the 60Hz timers
+    pub ips: u64,
+    pub unknown_opcode: OpcodePolicy,
+    // Read when the Chip is created, None seeds from the OS
+    pub seed: Option<u64>,
+    pub instruction_set: InstructionSet,
+    // Id of the platform profile in use, if any
+    pub platform: Option<String>,
+    forced_platform: Option<String>,
+    // Whether 00FF can switch to 128x64, only when the platform lists it
+    pub hires: bool,
     // quirks
     pub shift: bool,
     pub memory_increment_by_x: bool,
@@ -16,10 +77,21 @@ pub struct Config {
     pub logic: bool,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
+}
+
 impl Config {
     const DEFAULT_CONFIG: Config = Config {
-        rate: 60,
-        ipf: 12,
+        ips: 720,
+        unknown_opcode: OpcodePolicy::Ignore,
+        seed: None,
+        instruction_set: InstructionSet::Chip8,
+        platform: None,
+        forced_platform: None,
+        hires: true,
         shift: false,
         memory_increment_by_x: false,
         memory_leave_i_unchanged: false,
@@ -29,69 +101,173 @@ impl Config {
         logic: true,
     };
 
+    // Timers, display refresh and the vblank quirk run at this rate
+    pub const TIMER_HZ: u64 = 60;
+
     pub fn new() -> Config {
         Config::DEFAULT_CONFIG
     }
 
-    pub fn adjust_to_rom(&mut self, rom: &Vec<u8>) {
-        if let Some(platform) = self.get_platform(&rom) {
-            let quirks = match &*platform {
-                "originalChip8" | "hybridVIP" | "chip8x" => {
-                    (false, false, false, false, false, true, true)
-                }
-                "modernChip8" => (false, false, false, false, false, false, false),
-                "chip48" => (true, true, false, false, true, false, false),
-                "superchip1" => (true, true, false, false, true, false, false),
-                "superchip" => (true, false, true, false, true, false, false),
-                _ => (
-                    self.shift,
-                    self.memory_increment_by_x,
-                    self.memory_leave_i_unchanged,
-                    self.wrap,
-                    self.jump,
-                    self.vblank,
-                    self.logic,
-                ),
-            };
-
-            (
-                self.shift,
-                self.memory_increment_by_x,
-                self.memory_leave_i_unchanged,
-                self.wrap,
-                self.jump,
-                self.vblank,
-                self.logic,
-            ) = quirks;
+    // Applies the profile of the platform the ROM was made for: the one forced
+    // with force_platform, else the user's override, else the one found in the
+    // program database. A tickrate given for the ROM itself wins over the
+    // platform default
+    pub fn adjust_to_rom(&mut self, metadata: Option<&RomMetadata>, user_platform: Option<&str>) {
+        let id = self
+            .forced_platform
+            .as_deref()
+            .or(user_platform)
+            .or(metadata.and_then(RomMetadata::platform));
+
+        if let Some(platform) = id.and_then(Platform::find) {
+            self.apply_platform(platform);
+        }
+
+        if let Some(tickrate) = metadata.and_then(|metadata| metadata.rom.tickrate) {
+            self.ips = tickrate * Config::TIMER_HZ;
         }
     }
 
-    fn get_platform(&self, rom: &Vec<u8>) -> Option<String> {
-        let hash = self.get_sha1(rom);
+    // Uses the given platform instead of looking the ROM up, fails for unknown ids
+    pub fn force_platform(&mut self, id: &str) -> Result<(), String> {
+        if Platform::find(id).is_none() {
+            let known: Vec<&str> = Platform::all().iter().map(|p| p.id.as_str()).collect();
+            return Err(format!(
+                "Unknown platform '{}', expected one of: {}",
+                id,
+                known.join(", ")
+            ));
+        }
 
-        let data = fs::read_to_string("./db/sha1-hashes.json").ok()?;
-        let hashes: Value = serde_json::from_str(&data).ok()?;
+        self.forced_platform = Some(id.to_string());
+        Ok(())
+    }
 
-        let program_index = hashes.get(&hash)?.as_u64()? as usize;
+    pub fn is_platform_forced(&self) -> bool {
+        self.forced_platform.is_some()
+    }
 
-        let data = fs::read_to_string("./db/programs.json").ok()?;
-        let programs: Value = serde_json::from_str(&data).ok()?;
+    pub fn apply_platform(&mut self, platform: &Platform) {
+        self.platform = Some(platform.id.clone());
+        self.instruction_set = platform.instruction_set();
+        self.ips = platform.default_tickrate * Config::TIMER_HZ;
+        self.hires = platform.has_resolution(Platform::HIRES);
 
-        let platform = programs
-            .get(program_index)?
-            .get("roms")?
-            .get(&hash)?
-            .get("platforms")?
-            .get(0)?
-            .as_str()?;
+        self.set_quirks(platform.quirks);
+    }
 
-        Some(platform.to_string())
+    pub fn quirks(&self) -> Quirks {
+        Quirks {
+            shift: self.shift,
+            memory_increment_by_x: self.memory_increment_by_x,
+            memory_leave_i_unchanged: self.memory_leave_i_unchanged,
+            wrap: self.wrap,
+            jump: self.jump,
+            vblank: self.vblank,
+            logic: self.logic,
+        }
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.shift = quirks.shift;
+        self.memory_increment_by_x = quirks.memory_increment_by_x;
+        self.memory_leave_i_unchanged = quirks.memory_leave_i_unchanged;
+        self.wrap = quirks.wrap;
+        self.jump = quirks.jump;
+        self.vblank = quirks.vblank;
+        self.logic = quirks.logic;
+    }
+
+    // The seed isn't saved, the state of the generator is
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u64(self.ips);
+        w.u8(self.unknown_opcode as u8);
+        w.u8(self.instruction_set as u8);
+        w.option(self.platform.as_deref(), StateWriter::string);
+        for quirk in [
+            self.shift,
+            self.memory_increment_by_x,
+            self.memory_leave_i_unchanged,
+            self.wrap,
+            self.jump,
+            self.vblank,
+            self.logic,
+        ] {
+            w.bool(quirk);
+        }
     }
 
-    fn get_sha1(&self, rom: &Vec<u8>) -> String {
-        let mut hasher = sha1::Sha1::new();
-        hasher.update(&rom);
-        let result = hasher.finalize();
-        result.iter().map(|byte| format!("{:02x}", byte)).collect()
+    // Decodes into a copy, a state that fails partway leaves this config alone
+    pub fn load_state(&self, r: &mut StateReader) -> Result<Config, SaveStateError> {
+        let mut config = self.clone();
+        config.ips = r.u64()?;
+        config.unknown_opcode = match r.u8()? {
+            0 => OpcodePolicy::Ignore,
+            1 => OpcodePolicy::Log,
+            2 => OpcodePolicy::Halt,
+            _ => return Err(SaveStateError::Invalid("opcode policy")),
+        };
+        config.instruction_set = match r.u8()? {
+            0 => InstructionSet::Chip8,
+            1 => InstructionSet::SuperChip,
+            2 => InstructionSet::XoChip,
+            3 => InstructionSet::Chip8X,
+            _ => return Err(SaveStateError::Invalid("instruction set")),
+        };
+        config.platform = r.option(StateReader::string)?;
+        config.hires = config
+            .platform
+            .as_deref()
+            .and_then(Platform::find)
+            .is_none_or(|platform| platform.has_resolution(Platform::HIRES));
+        config.shift = r.bool()?;
+        config.memory_increment_by_x = r.bool()?;
+        config.memory_leave_i_unchanged = r.bool()?;
+        config.wrap = r.bool()?;
+        config.jump = r.bool()?;
+        config.vblank = r.bool()?;
+        config.logic = r.bool()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::Database;
+
+    #[test]
+    fn forced_platform() {
+        let mut config = Config::new();
+        assert!(config.force_platform("megachip9").is_err());
+
+        config.force_platform("xochip").unwrap();
+        config.adjust_to_rom(None, Some("superchip"));
+        assert_eq!(config.platform.as_deref(), Some("xochip"));
+        assert_eq!(config.instruction_set, InstructionSet::XoChip);
+        assert_eq!(config.ips, 100 * Config::TIMER_HZ);
+        assert!(config.wrap);
+        assert!(!config.vblank);
+        assert!(config.hires);
+
+        config.apply_platform(Platform::find("chip48").unwrap());
+        assert!(!config.hires);
+    }
+
+    #[test]
+    fn rom_tickrate() {
+        let rom = include_bytes!("../roms/connect4.ch8");
+        let mut metadata = Database::embedded()
+            .find(&crate::database::sha1(rom))
+            .unwrap();
+        metadata.rom.tickrate = Some(15);
+
+        let mut config = Config::new();
+        config.adjust_to_rom(Some(&metadata), None);
+        assert_eq!(config.platform.as_deref(), Some("superchip"));
+        assert_eq!(config.ips, 15 * Config::TIMER_HZ);
+
+        config.adjust_to_rom(Some(&metadata), Some("xochip"));
+        assert_eq!(config.platform.as_deref(), Some("xochip"));
     }
 }
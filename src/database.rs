@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+use sha1::Digest;
+
+use crate::display::Rgb;
+use crate::keypad::KeyHints;
+use crate::platform::Platform;
+
+// An entry of db/programs.json. A program can have several ROMs (versions, ports...)
+#[derive(Debug, Clone, Deserialize)]
+pub struct Program {
+    pub title: String,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rom {
+    pub file: String,
+    // platforms the ROM runs on, the first one is the preferred one
+    pub platforms: Vec<String>,
+    // instructions per 60Hz frame
+    pub tickrate: Option<u64>,
+    pub colors: Option<Colors>,
+    // CHIP-8 key for each action: up, down, left, right, a, b...
+    #[serde(default)]
+    pub keys: BTreeMap<String, u8>,
+    pub font_style: Option<String>,
+    pub embedded_title: Option<String>,
+    pub description: Option<String>,
+    pub release: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Colors {
+    // background, first plane, second plane and both planes
+    pub pixels: Vec<Rgb>,
+    // colour around the display while the sound timer is active
+    pub buzzer: Option<Rgb>,
+    // colour around the display otherwise
+    pub silence: Option<Rgb>,
+}
+
+// Everything the database knows about one ROM
+#[derive(Debug, Clone)]
+pub struct RomMetadata {
+    pub hash: String,
+    pub program: Program,
+    pub rom: Rom,
+}
+
+impl RomMetadata {
+    pub fn platform(&self) -> Option<&str> {
+        self.rom.platforms.first().map(String::as_str)
+    }
+
+    pub fn key_hints(&self) -> KeyHints {
+        KeyHints::from_names(&self.rom.keys)
+    }
+
+    // ROM level fields win over the program ones, a port can have other authors
+    pub fn authors(&self) -> &[String] {
+        if self.rom.authors.is_empty() {
+            &self.program.authors
+        } else {
+            &self.rom.authors
+        }
+    }
+
+    pub fn release(&self) -> Option<&str> {
+        self.rom
+            .release
+            .as_deref()
+            .or(self.program.release.as_deref())
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.rom
+            .description
+            .as_deref()
+            .or(self.program.description.as_deref())
+    }
+
+    // Instructions per frame, the ROM's own or else its platform's default
+    pub fn tickrate(&self) -> Option<u64> {
+        self.rom.tickrate.or_else(|| {
+            self.platform()
+                .and_then(Platform::find)
+                .map(|platform| platform.default_tickrate)
+        })
+    }
+}
+
+// The report printed by the info command
+impl fmt::Display for RomMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "SHA-1:     {}", self.hash)?;
+        writeln!(f, "Title:     {}", self.program.title)?;
+        if let Some(title) = &self.rom.embedded_title {
+            writeln!(f, "Embedded:  {}", title)?;
+        }
+        if !self.authors().is_empty() {
+            writeln!(f, "Authors:   {}", self.authors().join(", "))?;
+        }
+        if let Some(release) = self.release() {
+            writeln!(f, "Release:   {}", release)?;
+        }
+        writeln!(f, "Platforms: {}", self.rom.platforms.join(", "))?;
+        match (self.rom.tickrate, self.tickrate()) {
+            (Some(tickrate), _) => writeln!(f, "Tickrate:  {}", tickrate)?,
+            (None, Some(tickrate)) => writeln!(f, "Tickrate:  {} (platform default)", tickrate)?,
+            (None, None) => {}
+        }
+        let hints = self.key_hints();
+        if !hints.is_empty() {
+            writeln!(f, "Keys:      {}", hints)?;
+        }
+        writeln!(f, "File:      {}", self.rom.file)?;
+        if let Some(description) = self.description() {
+            write!(f, "\n{}", description.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+// db/programs.json and db/sha1-hashes.json, plus the user's overrides
+#[derive(Clone)]
+pub struct Database {
+    programs: Vec<Program>,
+    // SHA-1 of a ROM to its index in programs
+    hashes: HashMap<String, usize>,
+    // SHA-1 of a ROM to the id of the platform the user wants for it
+    overrides: HashMap<String, String>,
+    // why the overrides file was ignored, for the caller to report
+    overrides_error: Option<String>,
+}
+
+static EMBEDDED: OnceLock<Database> = OnceLock::new();
+static DATABASE: OnceLock<Database> = OnceLock::new();
+
+impl Database {
+    // Name of the overrides file, in the chip8-emulator config directory
+    pub const OVERRIDES_FILE: &'static str = "overrides.json";
+
+    // The database compiled into the binary, parsed on first use
+    pub fn embedded() -> &'static Database {
+        EMBEDDED.get_or_init(|| {
+            Database::from_json(
+                include_str!("../db/programs.json"),
+                include_str!("../db/sha1-hashes.json"),
+            )
+            .expect("the program database is invalid")
+        })
+    }
+
+    // The embedded database with the user's overrides file on top. Tests use
+    // embedded(), so a developer's overrides don't change what they see.
+    pub fn get() -> &'static Database {
+        DATABASE.get_or_init(|| {
+            let mut database = Database::embedded().clone();
+
+            if let Some(path) = Database::overrides_path().filter(|path| path.exists()) {
+                if let Err(e) = database.load_overrides(&path) {
+                    database.overrides_error = Some(format!("Ignoring {}: {}", path.display(), e));
+                }
+            }
+
+            database
+        })
+    }
+
+    pub fn from_json(programs: &str, hashes: &str) -> Result<Database, serde_json::Error> {
+        Ok(Database {
+            programs: serde_json::from_str(programs)?,
+            hashes: serde_json::from_str(hashes)?,
+            overrides: HashMap::new(),
+            overrides_error: None,
+        })
+    }
+
+    // $XDG_CONFIG_HOME/chip8-emulator/overrides.json, ~/.config/... when unset
+    pub fn overrides_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+
+        Some(
+            config_dir
+                .join("chip8-emulator")
+                .join(Database::OVERRIDES_FILE),
+        )
+    }
+
+    // The file maps ROM hashes to platform ids, like {"<sha1>": "superchip"}
+    pub fn load_overrides(&mut self, path: &Path) -> Result<(), String> {
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        self.add_overrides(&data)
+    }
+
+    // Nothing is added unless every entry is valid
+    pub fn add_overrides(&mut self, json: &str) -> Result<(), String> {
+        let overrides: HashMap<String, String> =
+            serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+        if let Some((hash, platform)) = overrides
+            .iter()
+            .find(|(_, platform)| Platform::find(platform).is_none())
+        {
+            return Err(format!("unknown platform '{}' for {}", platform, hash));
+        }
+        self.overrides.extend(
+            overrides
+                .into_iter()
+                .map(|(hash, platform)| (hash.to_lowercase(), platform)),
+        );
+
+        Ok(())
+    }
+
+    pub fn overrides_error(&self) -> Option<&str> {
+        self.overrides_error.as_deref()
+    }
+
+    pub fn find(&self, hash: &str) -> Option<RomMetadata> {
+        let program = self.programs.get(*self.hashes.get(hash)?)?;
+        let rom = program.roms.get(hash)?.clone();
+
+        Some(RomMetadata {
+            hash: hash.to_string(),
+            program: program.clone(),
+            rom,
+        })
+    }
+
+    // Platform the user picked for the ROM in their overrides file
+    pub fn platform_override(&self, hash: &str) -> Option<&str> {
+        self.overrides.get(hash).map(String::as_str)
+    }
+}
+
+pub fn lookup(rom: &[u8]) -> Option<RomMetadata> {
+    Database::get().find(&sha1(rom))
+}
+
+pub fn sha1(rom: &[u8]) -> String {
+    let mut hasher = sha1::Sha1::new();
+    hasher.update(rom);
+    let result = hasher.finalize();
+    result.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookup_rom() {
+        let rom = include_bytes!("../roms/breakout.ch8");
+        let metadata = Database::embedded().find(&sha1(rom)).unwrap();
+        assert_eq!(metadata.hash, sha1(rom));
+        assert!(metadata.program.title.starts_with("Breakout"));
+        assert!(metadata.platform().is_some());
+    }
+
+    #[test]
+    fn info() {
+        let metadata = Database::embedded()
+            .find(&sha1(include_bytes!("../roms/breakout.ch8")))
+            .unwrap();
+        assert_eq!(metadata.authors(), ["David Winter"]);
+        assert_eq!(metadata.release(), Some("1997"));
+
+        let info = metadata.to_string();
+        assert!(info.contains("Title:     Breakout\n"));
+        assert!(info.contains("Platforms: originalChip8, modernChip8\n"));
+        assert!(info.contains("Tickrate:  15 (platform default)\n"));
+        assert!(info.contains("Keys:      left: 4, right: 6\n"));
+        assert!(info.ends_with("on the Atari 2600 console."));
+    }
+
+    #[test]
+    fn colors() {
+        let rom: Rom = serde_json::from_str(
+            r##"{
+                "file": "octojam1title.ch8",
+                "platforms": ["superchip", "xochip"],
+                "tickrate": 7,
+                "colors": {"pixels": ["#aa4400", "#ffaa00"], "buzzer": "#ffaa00", "silence": "#000000"}
+            }"##,
+        )
+        .unwrap();
+        let colors = rom.colors.unwrap();
+        assert_eq!(
+            colors.pixels,
+            [Rgb(0xaa, 0x44, 0x00), Rgb(0xff, 0xaa, 0x00)]
+        );
+        assert_eq!(colors.silence, Some(Rgb(0, 0, 0)));
+        assert_eq!(rom.tickrate, Some(7));
+    }
+
+    #[test]
+    fn unknown_rom() {
+        assert!(Database::embedded().find(&sha1(&[0x12, 0x00])).is_none());
+    }
+
+    #[test]
+    fn overrides() {
+        let mut database = Database::from_json("[]", "{}").unwrap();
+        let hash = sha1(&[0x12, 0x00]);
+
+        database
+            .add_overrides(&format!(r#"{{"{}": "xochip"}}"#, hash.to_uppercase()))
+            .unwrap();
+        assert_eq!(database.platform_override(&hash), Some("xochip"));
+        assert!(database.find(&hash).is_none());
+
+        assert!(database.add_overrides(r#"{"abc": "chip9"}"#).is_err());
+        assert!(database.add_overrides("[]").is_err());
+
+        // a bad entry leaves the others out too
+        let other = sha1(&[0x13, 0x00]);
+        assert!(database
+            .add_overrides(&format!(r#"{{"{}": "chip48", "abc": "chip9"}}"#, other))
+            .is_err());
+        assert_eq!(database.platform_override(&other), None);
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::io::BufRead;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use crate::chip::Chip;
+use crate::disasm;
+use crate::error::ChipError;
+use crate::watch::Watchpoint;
+
+// What the debugger can be asked to do, the REPL parses them from stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Continue,
+    Pause,
+    Step,
+    // Runs a 2nnn call until it returns, other instructions are stepped
+    StepOver,
+    // Runs until the current subroutine returns
+    Finish,
+    // None lists the breakpoints
+    Break(Option<u16>),
+    Delete(u16),
+    Show,
+    // Disassembly around the address, PC when None
+    List(Option<u16>),
+    // None lists the watchpoints
+    Watch(Option<Watchpoint>),
+    Unwatch(Watchpoint),
+    Quit,
+    Help,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.trim();
+        let (command, argument) = match line.split_once(char::is_whitespace) {
+            Some((command, argument)) => (command, Some(argument.trim())),
+            None if line.is_empty() => ("step", None),
+            None => (line, None),
+        };
+        let address = argument.map(parse_address).transpose();
+
+        match (command, argument) {
+            ("c" | "continue", None) => Ok(Command::Continue),
+            ("p" | "pause", None) => Ok(Command::Pause),
+            ("s" | "step", None) => Ok(Command::Step),
+            ("n" | "next", None) => Ok(Command::StepOver),
+            ("f" | "finish", None) => Ok(Command::Finish),
+            ("b" | "break", _) => Ok(Command::Break(address?)),
+            ("d" | "delete", Some(argument)) => Ok(Command::Delete(parse_address(argument)?)),
+            ("r" | "regs", None) => Ok(Command::Show),
+            ("l" | "list", _) => Ok(Command::List(address?)),
+            ("w" | "watch", _) => Ok(Command::Watch(argument.map(str::parse).transpose()?)),
+            ("u" | "unwatch", Some(argument)) => Ok(Command::Unwatch(argument.parse()?)),
+            ("q" | "quit", None) => Ok(Command::Quit),
+            ("h" | "help", None) => Ok(Command::Help),
+            _ => Err(format!("Unknown command '{}', try help", line)),
+        }
+    }
+}
+
+// Hex, with or without 0x
+pub fn parse_address(word: &str) -> Result<u16, String> {
+    let digits = word.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address '{}'", word))
+}
+
+const HELP: &str = "\
+c, continue      run until a breakpoint or the pause hotkey
+p, pause         stop running
+s, step          run one instruction (also an empty line)
+n, next          step, running 2nnn calls until they return
+f, finish        run until the current subroutine returns
+b, break [addr]  add a breakpoint at addr (hex), or list them
+d, delete addr   remove the breakpoint at addr
+r, regs          show registers, timers, stack and code around PC
+l, list [addr]   show the code around addr, PC by default
+w, watch [what]  watch vX or i for changes, or addr[-end] [r|w|rw]
+                 for memory accesses (writes by default), or list them
+u, unwatch what  remove a watchpoint
+q, quit          stop the emulator";
+
+// Where a running debugger stops on its own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    // the instruction after a call, once the stack is back to sp
+    Return { pc: u16, sp: usize },
+    // after a return leaves the stack below sp
+    Finish { sp: usize },
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    paused: bool,
+    target: Option<Target>,
+    // PC we resumed from, its breakpoint doesn't stop us again
+    resumed_at: Option<u16>,
+    // lines typed in the terminal, read on another thread so the window stays responsive
+    stdin: Option<Receiver<String>>,
+}
+
+impl Debugger {
+    // Instructions shown before and after PC
+    const LISTING_CONTEXT: u16 = 5;
+
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.target = None;
+    }
+
+    pub fn resume(&mut self, chip: &Chip) {
+        self.paused = false;
+        self.resumed_at = Some(chip.pc());
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Called before each instruction while running. Returns why the
+    // emulator should stop before running it, if it should.
+    pub fn check(&mut self, chip: &Chip) -> Option<String> {
+        let pc = chip.pc();
+        let resumed_here = self.resumed_at.take() == Some(pc);
+
+        let reason = match self.target {
+            Some(Target::Return { pc: target, sp }) if pc == target && chip.sp() == sp => {
+                Some("stepped over the call".to_string())
+            }
+            Some(Target::Finish { sp }) if chip.sp() < sp => {
+                Some("returned from the subroutine".to_string())
+            }
+            _ if !resumed_here && self.breakpoints.contains(&pc) => {
+                Some(format!("breakpoint at {:#06X}", pc))
+            }
+            _ => None,
+        };
+
+        if reason.is_some() {
+            self.pause();
+        }
+        reason
+    }
+
+    // Called after each instruction while running. Describes the
+    // watchpoints it hit, with the instruction, and pauses on them.
+    pub fn check_watchpoints(&mut self, chip: &Chip) -> Option<String> {
+        if chip.watch_hits().is_empty() {
+            return None;
+        }
+
+        let set = chip.config().instruction_set;
+        let lines: Vec<String> = chip
+            .watch_hits()
+            .iter()
+            .map(|hit| {
+                let address = hit.pc as usize;
+                let instruction = match chip.opcode_at(address) {
+                    Some(opcode) => {
+                        let text = disasm::decode(&chip.memory()[address..], set)
+                            .map(|(text, _)| text)
+                            .unwrap_or_else(|| "(data)".to_string());
+                        format!("{:04X}  {}", opcode, text)
+                    }
+                    None => "(out of memory)".to_string(),
+                };
+                format!("{} by {:#06X}  {}", hit, hit.pc, instruction)
+            })
+            .collect();
+
+        self.pause();
+        Some(lines.join("\n"))
+    }
+
+    // Prints why the emulator stopped and where
+    pub fn report_pause(&self, chip: &Chip, reason: &str) {
+        println!("\nPaused, {}\n{}", reason, self.status(chip));
+        print_prompt();
+    }
+
+    // Runs a command and returns what to print
+    pub fn execute(&mut self, command: Command, chip: &mut Chip) -> Result<String, ChipError> {
+        let output = match command {
+            Command::Continue => {
+                self.target = None;
+                self.resume(chip);
+                "Running".to_string()
+            }
+            Command::Pause => {
+                self.pause();
+                self.status(chip)
+            }
+            Command::Step => {
+                self.pause();
+                chip.update()?;
+                match self.check_watchpoints(chip) {
+                    Some(hits) => format!("{}\n{}", hits, self.status(chip)),
+                    None => self.status(chip),
+                }
+            }
+            Command::StepOver => {
+                let opcode = chip.opcode_at(chip.pc() as usize);
+                if opcode.is_some_and(|opcode| opcode & 0xF000 == 0x2000) {
+                    self.target = Some(Target::Return {
+                        pc: chip.pc() + 2,
+                        sp: chip.sp(),
+                    });
+                    self.resume(chip);
+                    "Running until the call returns".to_string()
+                } else {
+                    self.execute(Command::Step, chip)?
+                }
+            }
+            Command::Finish => {
+                if chip.sp() == 0 {
+                    "Not in a subroutine".to_string()
+                } else {
+                    self.target = Some(Target::Finish { sp: chip.sp() });
+                    self.resume(chip);
+                    "Running until the subroutine returns".to_string()
+                }
+            }
+            Command::Break(Some(address)) => {
+                self.add_breakpoint(address);
+                format!("Breakpoint at {:#06X}", address)
+            }
+            Command::Break(None) => {
+                let list: Vec<String> = self.breakpoints().map(|a| format!("{:#06X}", a)).collect();
+                match list.is_empty() {
+                    true => "No breakpoints".to_string(),
+                    false => format!("Breakpoints: {}", list.join(", ")),
+                }
+            }
+            Command::Delete(address) => match self.remove_breakpoint(address) {
+                true => format!("Removed the breakpoint at {:#06X}", address),
+                false => format!("No breakpoint at {:#06X}", address),
+            },
+            Command::Watch(Some(watchpoint)) => {
+                chip.add_watchpoint(watchpoint);
+                format!("Watching {}", watchpoint)
+            }
+            Command::Watch(None) => {
+                let list: Vec<String> = chip.watchpoints().iter().map(|w| w.to_string()).collect();
+                match list.is_empty() {
+                    true => "No watchpoints".to_string(),
+                    false => format!("Watchpoints: {}", list.join(", ")),
+                }
+            }
+            Command::Unwatch(watchpoint) => match chip.remove_watchpoint(&watchpoint) {
+                true => format!("Stopped watching {}", watchpoint),
+                false => format!("{} isn't watched", watchpoint),
+            },
+            Command::Show => self.status(chip),
+            Command::List(address) => self.listing(chip, address.unwrap_or(chip.pc())),
+            Command::Quit => {
+                chip.stop();
+                "Quitting".to_string()
+            }
+            Command::Help => HELP.to_string(),
+        };
+
+        Ok(output)
+    }
+
+    // Runs the commands typed in the terminal since the last call
+    pub fn poll_stdin(&mut self, chip: &mut Chip) -> Result<(), ChipError> {
+        let stdin = self.stdin.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            thread::spawn(move || {
+                for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+            receiver
+        });
+
+        let lines: Vec<String> = stdin.try_iter().collect();
+        for line in lines {
+            match line.parse() {
+                Ok(command) => println!("{}", self.execute(command, chip)?),
+                Err(e) => println!("{}", e),
+            }
+            if self.paused {
+                print_prompt();
+            }
+        }
+        Ok(())
+    }
+
+    // Registers, timers, stack and the code around PC
+    pub fn status(&self, chip: &Chip) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "PC {:#06X}  I {:#06X}  SP {}  DT {:02X}  ST {:02X}",
+            chip.pc(),
+            chip.i(),
+            chip.sp(),
+            chip.delay_timer(),
+            chip.sound_timer()
+        );
+        let regs: Vec<String> = chip
+            .registers()
+            .iter()
+            .enumerate()
+            .map(|(x, v)| format!("V{:X} {:02X}", x, v))
+            .collect();
+        let _ = writeln!(out, "{}", regs[..8].join("  "));
+        let _ = writeln!(out, "{}", regs[8..].join("  "));
+        let stack: Vec<String> = chip.stack().iter().map(|a| format!("{:#06X}", a)).collect();
+        let _ = writeln!(out, "Stack: {}", stack.join(" "));
+        out + &self.listing(chip, chip.pc())
+    }
+
+    // A window of instructions around address, aligned on it
+    pub fn listing(&self, chip: &Chip, address: u16) -> String {
+        let set = chip.config().instruction_set;
+        let memory = chip.memory();
+        let start = address.saturating_sub(2 * Debugger::LISTING_CONTEXT);
+
+        let mut out = String::new();
+        for line in 0..=2 * Debugger::LISTING_CONTEXT {
+            let current = start as usize + 2 * line as usize;
+            if current + 1 >= memory.len() {
+                break;
+            }
+            let text = disasm::decode(&memory[current..], set)
+                .map(|(text, _)| text)
+                .unwrap_or_else(|| "(data)".to_string());
+            let _ = writeln!(
+                out,
+                "{}{} {:#06X}  {:02X}{:02X}  {}",
+                if current == chip.pc() as usize {
+                    ">"
+                } else {
+                    " "
+                },
+                if self.breakpoints.contains(&(current as u16)) {
+                    "*"
+                } else {
+                    " "
+                },
+                current,
+                memory[current],
+                memory[current + 1],
+                text
+            );
+        }
+        out.pop();
+        out
+    }
+}
+
+pub fn print_prompt() {
+    use std::io::Write;
+    print!("(debug) ");
+    let _ = std::io::stdout().flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CALL 0x206; JP 0x202 (loops); 0x204: unused; 0x206: V0 += 1; RET
+    const ROM: [u8; 10] = [0x22, 0x06, 0x12, 0x02, 0x00, 0x00, 0x70, 0x01, 0x00, 0xEE];
+
+    fn run_instructions(debugger: &mut Debugger, chip: &mut Chip, max: usize) -> Option<String> {
+        for _ in 0..max {
+            if let Some(reason) = debugger.check(chip) {
+                return Some(reason);
+            }
+            chip.update().unwrap();
+        }
+        None
+    }
+
+    fn init() -> (Debugger, Chip) {
+        let mut chip = Chip::new();
+        chip.load_rom(&ROM).unwrap();
+        (Debugger::new(), chip)
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!("b 2a0".parse(), Ok(Command::Break(Some(0x2A0))));
+        assert_eq!("".parse(), Ok(Command::Step));
+        assert_eq!("next".parse(), Ok(Command::StepOver));
+        assert_eq!("l 0x300".parse(), Ok(Command::List(Some(0x300))));
+        assert_eq!(
+            "w v3".parse(),
+            Ok(Command::Watch(Some(Watchpoint::Register(3))))
+        );
+        assert_eq!("watch".parse(), Ok(Command::Watch(None)));
+        assert!("u".parse::<Command>().is_err());
+        assert!("d".parse::<Command>().is_err());
+        assert!("jump".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut debugger, mut chip) = init();
+        debugger
+            .execute(Command::Break(Some(0x206)), &mut chip)
+            .unwrap();
+
+        let reason = run_instructions(&mut debugger, &mut chip, 10).unwrap();
+        assert_eq!(reason, "breakpoint at 0x0206");
+        assert!(debugger.is_paused());
+        assert_eq!(chip.sp(), 1);
+
+        // continuing doesn't stop on the breakpoint we're at
+        debugger.execute(Command::Continue, &mut chip).unwrap();
+        assert!(run_instructions(&mut debugger, &mut chip, 2).is_none());
+    }
+
+    #[test]
+    fn step_over_and_finish() {
+        let (mut debugger, mut chip) = init();
+        debugger.execute(Command::StepOver, &mut chip).unwrap();
+        assert!(!debugger.is_paused());
+        let reason = run_instructions(&mut debugger, &mut chip, 10).unwrap();
+        assert_eq!(reason, "stepped over the call");
+        assert_eq!(chip.pc(), 0x202);
+        assert_eq!(chip.registers()[0], 1);
+
+        // into the call, then run to its return
+        let (mut debugger, mut chip) = init();
+        debugger.execute(Command::Step, &mut chip).unwrap();
+        assert_eq!(chip.pc(), 0x206);
+        debugger.execute(Command::Finish, &mut chip).unwrap();
+        let reason = run_instructions(&mut debugger, &mut chip, 10).unwrap();
+        assert_eq!(reason, "returned from the subroutine");
+        assert_eq!(chip.pc(), 0x202);
+    }
+
+    #[test]
+    fn status() {
+        let (mut debugger, mut chip) = init();
+        debugger.add_breakpoint(0x206);
+        let status = debugger.status(&chip);
+        assert!(status.starts_with("PC 0x0200  I 0x0000  SP 0"));
+        assert!(status.contains(">  0x0200  2206  :call 0x206"));
+        assert!(status.contains(" * 0x0206  7001  v0 += 0x01"));
+
+        debugger.execute(Command::Step, &mut chip).unwrap();
+        assert!(debugger.status(&chip).contains("Stack: 0x0202"));
+    }
+
+    #[test]
+    fn watchpoints() {
+        // I = 0x300; V0 = 5; save v0; load v0
+        let rom = [0xA3, 0x00, 0x60, 0x05, 0xF0, 0x55, 0xA3, 0x00, 0xF0, 0x65];
+        let mut chip = Chip::new();
+        chip.config_mut().memory_leave_i_unchanged = true;
+        chip.load_rom(&rom).unwrap();
+        let mut debugger = Debugger::new();
+        for command in ["w 300 rw", "w v0", "w i"] {
+            debugger
+                .execute(command.parse().unwrap(), &mut chip)
+                .unwrap();
+        }
+
+        let mut hits = Vec::new();
+        while chip.pc() < 0x20A {
+            chip.update().unwrap();
+            hits.extend(debugger.check_watchpoints(&chip));
+        }
+        assert_eq!(
+            hits,
+            [
+                "I changed: 0x0000 -> 0x0300 by 0x0200  A300  i := 0x300",
+                "V0 changed: 0x00 -> 0x05 by 0x0202  6005  v0 := 0x05",
+                "0x0300 written: 0x00 -> 0x05 by 0x0204  F055  save v0",
+                "0x0300 read: 0x05 by 0x0208  F065  load v0",
+            ]
+        );
+        assert!(debugger.is_paused());
+    }
+}
@@ -0,0 +1,246 @@
+// Guesses the platform of ROMs the program database doesn't know, from the
+// opcodes they use and their file extension. Only the code reachable from the
+// start of the program is looked at, so sprite data isn't taken for opcodes.
+// Jumps through Bnnn can't be followed, so some code may be missed.
+
+use crate::config::InstructionSet;
+use crate::disasm;
+
+// Result of scanning a ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Detection {
+    pub platform: &'static str,
+    // 0 when nothing points away from CHIP-8, closer to 1 with more evidence
+    pub confidence: f64,
+    // What the guess is based on, e.g. "00FE/00FF (resolution) at 0x204"
+    pub reasons: Vec<String>,
+}
+
+impl Detection {
+    // Below this the guess isn't applied
+    pub const THRESHOLD: f64 = 0.5;
+
+    pub fn is_confident(&self) -> bool {
+        self.confidence >= Detection::THRESHOLD
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    SuperChip,
+    XoChip,
+    Chip8X,
+}
+
+impl Family {
+    // Families whose evidence supports a guess of this one.
+    // XO-CHIP is a superset of SUPER-CHIP.
+    fn supported_by(self) -> &'static [Family] {
+        match self {
+            Family::SuperChip => &[Family::SuperChip],
+            Family::XoChip => &[Family::XoChip, Family::SuperChip],
+            Family::Chip8X => &[Family::Chip8X],
+        }
+    }
+
+    fn platform(self) -> &'static str {
+        match self {
+            Family::SuperChip => "superchip",
+            Family::XoChip => "xochip",
+            Family::Chip8X => "chip8x",
+        }
+    }
+}
+
+struct Evidence {
+    family: Family,
+    weight: f64,
+    kind: &'static str,
+    // None for the file extension
+    address: Option<usize>,
+}
+
+// Each kind of evidence counts once, however many times it's found
+#[derive(Default)]
+struct Scan {
+    evidence: Vec<Evidence>,
+}
+
+impl Scan {
+    fn add(&mut self, family: Family, weight: f64, kind: &'static str, address: Option<usize>) {
+        if !self.evidence.iter().any(|e| e.kind == kind) {
+            self.evidence.push(Evidence {
+                family,
+                weight,
+                kind,
+                address,
+            });
+        }
+    }
+
+    fn score(&self, family: Family) -> f64 {
+        self.evidence
+            .iter()
+            .filter(|e| family.supported_by().contains(&e.family))
+            .map(|e| e.weight)
+            .sum()
+    }
+}
+
+// The ROM is scanned from start, the address it's loaded at
+pub fn detect(rom: &[u8], extension: Option<&str>, start: usize) -> Detection {
+    let mut scan = Scan::default();
+
+    match extension.map(str::to_lowercase).as_deref() {
+        Some("sc8") => scan.add(Family::SuperChip, 2.0, "file extension .sc8", None),
+        Some("xo8") => scan.add(Family::XoChip, 2.0, "file extension .xo8", None),
+        Some("c8x") => scan.add(Family::Chip8X, 2.0, "file extension .c8x", None),
+        _ => {}
+    }
+
+    // XO-CHIP is the widest set, so that its opcodes are followed too
+    for address in disasm::reachable(rom, start, InstructionSet::XoChip) {
+        let offset = address - start;
+        let opcode = u16::from_be_bytes([rom[offset], rom[offset + 1]]);
+        let at = Some(address);
+        let x = (opcode >> 8) & 0xF;
+
+        match (opcode & 0xF000, opcode & 0x00FF, opcode & 0x000F) {
+            _ if opcode == 0x00FE || opcode == 0x00FF => {
+                scan.add(Family::SuperChip, 1.0, "00FE/00FF (resolution)", at)
+            }
+            _ if opcode == 0x00FB || opcode == 0x00FC => {
+                scan.add(Family::SuperChip, 1.0, "00FB/00FC (scroll sideways)", at)
+            }
+            _ if (0x00C1..=0x00CF).contains(&opcode) => {
+                scan.add(Family::SuperChip, 1.0, "00Cn (scroll down)", at)
+            }
+            _ if opcode == 0x00FD => scan.add(Family::SuperChip, 0.5, "00FD (exit)", at),
+            _ if (0x00D1..=0x00DF).contains(&opcode) => {
+                scan.add(Family::XoChip, 1.0, "00Dn (scroll up)", at)
+            }
+            _ if opcode == 0x02A0 => scan.add(Family::Chip8X, 0.5, "02A0 (background)", at),
+            _ if opcode == 0xF000 => scan.add(Family::XoChip, 1.0, "F000 (long load)", at),
+            _ if opcode == 0xF002 => scan.add(Family::XoChip, 1.0, "F002 (audio)", at),
+            (0xF000, 0x01, _) if x <= 3 => scan.add(Family::XoChip, 1.0, "Fn01 (planes)", at),
+            (0xF000, 0x30, _) => scan.add(Family::SuperChip, 1.0, "Fx30 (big font)", at),
+            (0xF000, 0x75, _) | (0xF000, 0x85, _) => {
+                scan.add(Family::SuperChip, 1.0, "Fx75/Fx85 (flags)", at)
+            }
+            (0xF000, 0x3A, _) => scan.add(Family::XoChip, 1.0, "Fx3A (pitch)", at),
+            (0x5000, _, 2) | (0x5000, _, 3) => {
+                scan.add(Family::XoChip, 0.5, "5xy2/5xy3 (register ranges)", at)
+            }
+            (0xD000, _, 0) => scan.add(Family::SuperChip, 0.5, "Dxy0 (16x16 sprite)", at),
+            // vx >>= 1 leaving y unused, written for the SUPER-CHIP shift quirk
+            (0x8000, 0x06, _) | (0x8000, 0x0E, _) if x != 0 => {
+                scan.add(Family::SuperChip, 0.5, "8x06/8x0E (shift ignoring vy)", at)
+            }
+            _ => {}
+        }
+    }
+
+    // The rarer families first, as long as they have more than weak evidence
+    let order = [Family::XoChip, Family::Chip8X, Family::SuperChip];
+    let own_score = |family| -> f64 {
+        scan.evidence
+            .iter()
+            .filter(|e| e.family == family)
+            .map(|e| e.weight)
+            .sum()
+    };
+    let family = order
+        .into_iter()
+        .find(|&family| own_score(family) >= 1.0)
+        .or(order.into_iter().find(|&family| own_score(family) > 0.0));
+
+    let Some(family) = family else {
+        return Detection {
+            platform: "originalChip8",
+            confidence: 0.0,
+            reasons: Vec::new(),
+        };
+    };
+
+    let score = scan.score(family);
+    let reasons = scan
+        .evidence
+        .iter()
+        .filter(|e| family.supported_by().contains(&e.family))
+        .map(|e| match e.address {
+            Some(address) => format!("{} at {:#05X}", e.kind, address),
+            None => e.kind.to_string(),
+        })
+        .collect();
+
+    Detection {
+        platform: family.platform(),
+        confidence: score / (score + 1.0),
+        reasons,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip8_rom() {
+        let detection = detect(include_bytes!("../roms/ibm.ch8"), Some("ch8"), 0x200);
+        assert_eq!(detection.platform, "originalChip8");
+        assert!(!detection.is_confident());
+    }
+
+    #[test]
+    fn superchip_rom() {
+        let detection = detect(include_bytes!("../tests_roms/SCTEST.CH8"), None, 0x200);
+        assert_eq!(detection.platform, "superchip");
+        assert!(detection.is_confident());
+
+        let detection = detect(
+            include_bytes!("../tests_roms/SCTEST.CH8"),
+            Some("sc8"),
+            0x200,
+        );
+        assert!(detection.confidence >= 0.75);
+    }
+
+    #[test]
+    fn xochip_rom() {
+        // F000 0300, F201, 00FF
+        let rom = [0xF0, 0x00, 0x03, 0x00, 0xF2, 0x01, 0x00, 0xFF];
+        let detection = detect(&rom, None, 0x200);
+        assert_eq!(detection.platform, "xochip");
+        assert_eq!(detection.confidence, 0.75);
+        assert_eq!(detection.reasons[0], "F000 (long load) at 0x200");
+
+        let detection = detect(&[0x00, 0xE0], Some("XO8"), 0x200);
+        assert_eq!(detection.platform, "xochip");
+        assert!(detection.is_confident());
+    }
+
+    #[test]
+    fn unreachable_data() {
+        // JP 0x204, then what looks like 00FF but is never run
+        let detection = detect(&[0x12, 0x04, 0x00, 0xFF, 0x00, 0xE0], None, 0x200);
+        assert_eq!(detection.platform, "originalChip8");
+    }
+
+    #[test]
+    fn program_start() {
+        // JP 0x304, then 00FF, reached when the ROM is loaded at 0x300
+        let rom = [0x13, 0x04, 0x00, 0xE0, 0x00, 0xFF];
+        assert_eq!(detect(&rom, None, 0x200).platform, "originalChip8");
+        let detection = detect(&rom, None, 0x300);
+        assert_eq!(detection.platform, "superchip");
+        assert_eq!(detection.reasons[0], "00FE/00FF (resolution) at 0x304");
+    }
+
+    #[test]
+    fn weak_evidence() {
+        // a lone Dxy0 could be sprite data
+        let detection = detect(&[0xD0, 0x10], None, 0x200);
+        assert_eq!(detection.platform, "superchip");
+        assert!(!detection.is_confident());
+    }
+}
@@ -0,0 +1,329 @@
+// Turns ROMs and memory ranges into Octo assembly. Every line keeps its
+// address and raw bytes in a comment, and the output reassembles to the same
+// bytes: opcodes Octo can't express, and anything that isn't reachable code,
+// are written as plain bytes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::config::InstructionSet;
+
+// One line of a listing: an instruction or a run of data bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    // Set when something jumps to or calls this address
+    pub label: Option<String>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = &line.label {
+                writeln!(f, ": {}", label)?;
+            }
+            let raw: String = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            writeln!(f, "\t{:<27} # {:04X}  {}", line.text, line.address, raw)?;
+        }
+        Ok(())
+    }
+}
+
+// Data lines hold at most this many bytes
+const DATA_BYTES_PER_LINE: usize = 8;
+
+// Disassembles bytes loaded at origin. Only the code reachable from origin is
+// decoded, the rest is data.
+pub fn disassemble(bytes: &[u8], origin: usize, set: InstructionSet) -> Listing {
+    let code = reachable(bytes, origin, set);
+
+    // where each line starts, so that we know which targets can get a label
+    let mut starts = Vec::new();
+    let mut address = origin;
+    let end = origin + bytes.len();
+    while address < end {
+        starts.push(address);
+        address += match decode(&bytes[address - origin..], set) {
+            Some((_, len)) if code.contains(&address) => len,
+            _ => data_len(&code, address, end),
+        };
+    }
+
+    let mut labels = BTreeMap::new();
+    labels.insert(origin, "main".to_string());
+    for &address in &starts {
+        if !code.contains(&address) {
+            continue;
+        }
+        let Some(opcode) = word_at(bytes, origin, address) else {
+            continue;
+        };
+        let target = (opcode & 0x0FFF) as usize;
+        if starts.binary_search(&target).is_err() || target == origin {
+            continue;
+        }
+        match opcode >> 12 {
+            0x2 => {
+                labels.insert(target, format!("sub-{:03X}", target));
+            }
+            0x1 => {
+                labels
+                    .entry(target)
+                    .or_insert_with(|| format!("label-{:03X}", target));
+            }
+            _ => {}
+        }
+    }
+
+    let mut lines = Vec::new();
+    for &address in &starts {
+        let offset = address - origin;
+        let (text, len) = match decode_with_labels(&bytes[offset..], set, &labels) {
+            Some((text, len)) if code.contains(&address) => (text, len),
+            _ => {
+                let len = data_len(&code, address, end);
+                (data(&bytes[offset..offset + len]), len)
+            }
+        };
+        lines.push(Line {
+            address,
+            bytes: bytes[offset..offset + len].to_vec(),
+            label: labels.get(&address).cloned(),
+            text,
+        });
+    }
+
+    Listing { lines }
+}
+
+// Octo text of the instruction at the start of bytes, and its length in bytes.
+// Jump and call targets are written as numbers. None for anything that has to
+// be written as data.
+pub fn decode(bytes: &[u8], set: InstructionSet) -> Option<(String, usize)> {
+    decode_with_labels(bytes, set, &BTreeMap::new())
+}
+
+fn decode_with_labels(
+    bytes: &[u8],
+    set: InstructionSet,
+    labels: &BTreeMap<usize, String>,
+) -> Option<(String, usize)> {
+    let opcode = u16::from_be_bytes([*bytes.first()?, *bytes.get(1)?]);
+    let schip = set.has_superchip();
+    let xo = set.has_xochip();
+    let c8x = set.has_chip8x();
+
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    let text = match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xC, _) if schip && n > 0 => format!("scroll-down {}", n),
+        (0x0, 0x0, 0xD, _) if xo && n > 0 => format!("scroll-up {}", n),
+        (0x0, 0x0, 0xE, 0x0) => "clear".to_string(),
+        (0x0, 0x0, 0xE, 0xE) => "return".to_string(),
+        (0x0, 0x0, 0xF, 0xB) if schip => "scroll-right".to_string(),
+        (0x0, 0x0, 0xF, 0xC) if schip => "scroll-left".to_string(),
+        (0x0, 0x0, 0xF, 0xD) if schip => "exit".to_string(),
+        (0x0, 0x0, 0xF, 0xE) if schip => "lores".to_string(),
+        (0x0, 0x0, 0xF, 0xF) if schip => "hires".to_string(),
+        (0x1, _, _, _) => match labels.get(&(nnn as usize)) {
+            Some(label) => format!("jump {}", label),
+            None => format!("jump {:#05X}", nnn),
+        },
+        // Octo calls a subroutine by writing its name
+        (0x2, _, _, _) => match labels.get(&(nnn as usize)) {
+            Some(label) => label.clone(),
+            None => format!(":call {:#05X}", nnn),
+        },
+        (0x3, _, _, _) => format!("if v{:x} != {:#04X} then", x, kk),
+        (0x4, _, _, _) => format!("if v{:x} == {:#04X} then", x, kk),
+        (0x5, _, _, 0x0) => format!("if v{:x} != v{:x} then", x, y),
+        (0x5, _, _, 0x2) if xo => format!("save v{:x} - v{:x}", x, y),
+        (0x5, _, _, 0x3) if xo => format!("load v{:x} - v{:x}", x, y),
+        (0x6, _, _, _) => format!("v{:x} := {:#04X}", x, kk),
+        (0x7, _, _, _) => format!("v{:x} += {:#04X}", x, kk),
+        (0x8, _, _, 0x0) => format!("v{:x} := v{:x}", x, y),
+        (0x8, _, _, 0x1) => format!("v{:x} |= v{:x}", x, y),
+        (0x8, _, _, 0x2) => format!("v{:x} &= v{:x}", x, y),
+        (0x8, _, _, 0x3) => format!("v{:x} ^= v{:x}", x, y),
+        (0x8, _, _, 0x4) => format!("v{:x} += v{:x}", x, y),
+        (0x8, _, _, 0x5) => format!("v{:x} -= v{:x}", x, y),
+        (0x8, _, _, 0x6) => format!("v{:x} >>= v{:x}", x, y),
+        (0x8, _, _, 0x7) => format!("v{:x} =- v{:x}", x, y),
+        (0x8, _, _, 0xE) => format!("v{:x} <<= v{:x}", x, y),
+        (0x9, _, _, 0x0) => format!("if v{:x} == v{:x} then", x, y),
+        (0xA, _, _, _) => format!("i := {:#05X}", nnn),
+        // CHIP-8X colour opcodes have no Octo syntax
+        (0xB, _, _, _) if c8x => return None,
+        (0xB, _, _, _) => format!("jump0 {:#05X}", nnn),
+        (0xC, _, _, _) => format!("v{:x} := random {:#04X}", x, kk),
+        (0xD, _, _, _) => format!("sprite v{:x} v{:x} {}", x, y, n),
+        (0xE, _, 0x9, 0xE) => format!("if v{:x} -key then", x),
+        (0xE, _, 0xA, 0x1) => format!("if v{:x} key then", x),
+        (0xF, 0x0, 0x0, 0x0) if xo => {
+            let long = u16::from_be_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+            return Some((format!("i := long {:#06X}", long), 4));
+        }
+        (0xF, _, 0x0, 0x1) if xo && x <= 3 => format!("plane {}", x),
+        (0xF, 0x0, 0x0, 0x2) if xo => "audio".to_string(),
+        (0xF, _, 0x0, 0x7) => format!("v{:x} := delay", x),
+        (0xF, _, 0x0, 0xA) => format!("v{:x} := key", x),
+        (0xF, _, 0x1, 0x5) => format!("delay := v{:x}", x),
+        (0xF, _, 0x1, 0x8) => format!("buzzer := v{:x}", x),
+        (0xF, _, 0x1, 0xE) => format!("i += v{:x}", x),
+        (0xF, _, 0x2, 0x9) => format!("i := hex v{:x}", x),
+        (0xF, _, 0x3, 0x0) if schip => format!("i := bighex v{:x}", x),
+        (0xF, _, 0x3, 0x3) => format!("bcd v{:x}", x),
+        (0xF, _, 0x3, 0xA) if xo => format!("pitch := v{:x}", x),
+        (0xF, _, 0x5, 0x5) => format!("save v{:x}", x),
+        (0xF, _, 0x6, 0x5) => format!("load v{:x}", x),
+        (0xF, _, 0x7, 0x5) if schip => format!("saveflags v{:x}", x),
+        (0xF, _, 0x8, 0x5) if schip => format!("loadflags v{:x}", x),
+        _ => return None,
+    };
+
+    Some((text, 2))
+}
+
+// Addresses of the instructions on every path from origin. Bnnn jumps can't
+// be followed, so code only reached through them is missed.
+pub fn reachable(bytes: &[u8], origin: usize, set: InstructionSet) -> BTreeSet<usize> {
+    let end = origin + bytes.len();
+    let mut found = BTreeSet::new();
+    let mut pending = vec![origin];
+
+    while let Some(mut address) = pending.pop() {
+        while address >= origin && address + 1 < end && !found.contains(&address) {
+            found.insert(address);
+            let Some(opcode) = word_at(bytes, origin, address) else {
+                break;
+            };
+
+            let nnn = (opcode & 0x0FFF) as usize;
+            let long_load =
+                |address| set.has_xochip() && word_at(bytes, origin, address) == Some(0xF000);
+            match (opcode >> 12, opcode & 0x00FF, opcode & 0x000F) {
+                // return, exit, and jumps we can't follow
+                _ if opcode == 0x00EE => break,
+                _ if opcode == 0x00FD && set.has_superchip() => break,
+                (0xB, _, _) if !set.has_chip8x() => break,
+                (0x1, _, _) => {
+                    pending.push(nnn);
+                    break;
+                }
+                (0x2, _, _) => pending.push(nnn),
+                // skips jump over four bytes when the next opcode is F000
+                (0x3, _, _)
+                | (0x4, _, _)
+                | (0x5, _, 0)
+                | (0x9, _, 0)
+                | (0xE, 0x9E, _)
+                | (0xE, 0xA1, _) => {
+                    pending.push(if long_load(address + 2) {
+                        address + 6
+                    } else {
+                        address + 4
+                    });
+                }
+                _ if long_load(address) => address += 2,
+                _ => {}
+            }
+            address += 2;
+        }
+    }
+
+    found
+}
+
+fn word_at(bytes: &[u8], origin: usize, address: usize) -> Option<u16> {
+    let offset = address.checked_sub(origin)?;
+    Some(u16::from_be_bytes([
+        *bytes.get(offset)?,
+        *bytes.get(offset + 1)?,
+    ]))
+}
+
+// Data runs until the next instruction, or DATA_BYTES_PER_LINE bytes
+fn data_len(code: &BTreeSet<usize>, address: usize, end: usize) -> usize {
+    let next_code = code.range(address + 1..).next().copied().unwrap_or(end);
+    next_code.min(end).min(address + DATA_BYTES_PER_LINE) - address
+}
+
+fn data(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:#04X}", b)).collect();
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_opcodes() {
+        let set = InstructionSet::XoChip;
+        assert_eq!(decode(&[0x00, 0xE0], set), Some(("clear".to_string(), 2)));
+        assert_eq!(decode(&[0x3A, 0x05], set).unwrap().0, "if va != 0x05 then");
+        assert_eq!(decode(&[0x8A, 0xB7], set).unwrap().0, "va =- vb");
+        assert_eq!(decode(&[0xD0, 0x1F], set).unwrap().0, "sprite v0 v1 15");
+        assert_eq!(decode(&[0xF2, 0x01], set).unwrap().0, "plane 2");
+        assert_eq!(
+            decode(&[0xF0, 0x00, 0x12, 0x34], set),
+            Some(("i := long 0x1234".to_string(), 4))
+        );
+
+        // not valid, or no Octo syntax, on these platforms
+        assert_eq!(decode(&[0x00, 0xFF], InstructionSet::Chip8), None);
+        assert_eq!(decode(&[0x51, 0x22], InstructionSet::SuperChip), None);
+        assert_eq!(decode(&[0xB1, 0x23], InstructionSet::Chip8X), None);
+        assert_eq!(decode(&[0x00], set), None);
+    }
+
+    #[test]
+    fn labels_and_data() {
+        let rom = [
+            0x22, 0x08, // 200: call 208
+            0x12, 0x02, // 202: jump 202
+            0xFF, 0x81, 0x81, 0xFF, // 204: sprite data
+            0x60, 0x01, // 208: v0 := 1
+            0x00, 0xEE, // 20A: return
+        ];
+        let listing = disassemble(&rom, 0x200, InstructionSet::Chip8);
+        let text: Vec<&str> = listing.lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(
+            text,
+            [
+                "sub-208",
+                "jump label-202",
+                "0xFF 0x81 0x81 0xFF",
+                "v0 := 0x01",
+                "return"
+            ]
+        );
+        assert_eq!(listing.lines[0].label.as_deref(), Some("main"));
+        assert_eq!(listing.lines[1].label.as_deref(), Some("label-202"));
+        assert_eq!(listing.lines[3].label.as_deref(), Some("sub-208"));
+        assert_eq!(listing.lines[2].bytes, [0xFF, 0x81, 0x81, 0xFF]);
+
+        let output = listing.to_string();
+        assert!(output.starts_with(": main\n\tsub-208"));
+        assert!(output.contains("# 0204  FF8181FF\n"));
+    }
+
+    #[test]
+    fn skip_over_long_load() {
+        // if v0 != 0 then i := long 0x0300, then clear
+        let rom = [0x30, 0x00, 0xF0, 0x00, 0x03, 0x00, 0x00, 0xE0];
+        let code = reachable(&rom, 0x200, InstructionSet::XoChip);
+        assert_eq!(code.into_iter().collect::<Vec<_>>(), [0x200, 0x202, 0x206]);
+    }
+}
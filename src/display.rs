@@ -1,94 +1,491 @@
-use sdl2::render::WindowCanvas;
+use std::str::FromStr;
 
+use serde::de::{self, Deserialize, Deserializer};
+
+use crate::database::Colors;
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+// CHIP-8X colour attributes, kept apart from the monochrome pixels.
+// Colours are indexes into the VP-590 palette: black, red, blue, violet,
+// green, yellow, aqua and white.
+#[derive(Clone)]
+pub struct ColorLayer {
+    background: usize,
+    // foreground colour of each 8x1 pixel cell
+    cells: [[u8; ColorLayer::COLUMNS]; Display::LORES_HEIGHT],
+}
+
+impl Default for ColorLayer {
+    fn default() -> Self {
+        ColorLayer::new()
+    }
+}
+
+impl ColorLayer {
+    pub const CELL_WIDTH: usize = 8;
+    pub const COLUMNS: usize = Display::LORES_WIDTH / ColorLayer::CELL_WIDTH;
+    // 02A0 steps through these background colours
+    const BACKGROUNDS: [u8; 4] = [2, 0, 4, 1];
+    const DEFAULT_FOREGROUND: u8 = 1;
+
+    pub fn new() -> ColorLayer {
+        ColorLayer {
+            background: 0,
+            cells: [[ColorLayer::DEFAULT_FOREGROUND; ColorLayer::COLUMNS]; Display::LORES_HEIGHT],
+        }
+    }
+
+    pub fn background(&self) -> u8 {
+        ColorLayer::BACKGROUNDS[self.background]
+    }
+
+    pub fn next_background(&mut self) {
+        self.background = (self.background + 1) % ColorLayer::BACKGROUNDS.len();
+    }
+
+    pub fn foreground(&self, x: usize, y: usize) -> u8 {
+        self.cells[y % Display::LORES_HEIGHT][(x / ColorLayer::CELL_WIDTH) % ColorLayer::COLUMNS]
+    }
+
+    // Sets the foreground colour of a cell, cells outside of the screen are ignored
+    pub fn set_foreground(&mut self, column: usize, y: usize, color: u8) {
+        if column < ColorLayer::COLUMNS && y < Display::LORES_HEIGHT {
+            self.cells[y][column] = color & 0x7;
+        }
+    }
+}
+
+// A colour as written in the program database, "#rrggbb"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        let value = match hex.len() {
+            6 => u32::from_str_radix(hex, 16).ok(),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => Ok(Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8)),
+            None => Err(format!("Invalid colour '{}', expected #rrggbb", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rgb {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+// Colours a frontend should use for the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    // Indexed by Display::color, the last two are only used by XO-CHIP
+    pub pixels: [Rgb; 4],
+    // Around the display while the sound timer is active
+    pub buzzer: Rgb,
+    // Around the display otherwise
+    pub silence: Rgb,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::DEFAULT
+    }
+}
+
+impl Palette {
+    pub const DEFAULT: Palette = Palette {
+        pixels: [
+            Rgb(0, 0, 0),
+            Rgb(60, 163, 214),
+            Rgb(214, 111, 60),
+            Rgb(240, 240, 240),
+        ],
+        buzzer: Rgb(0, 0, 0),
+        silence: Rgb(0, 0, 0),
+    };
+
+    // Colours missing from the database entry keep their default value
+    pub fn from_colors(colors: &Colors) -> Palette {
+        let mut palette = Palette::DEFAULT;
+        for (pixel, color) in palette.pixels.iter_mut().zip(&colors.pixels) {
+            *pixel = *color;
+        }
+        palette.buzzer = colors.buzzer.unwrap_or(palette.buzzer);
+        palette.silence = colors.silence.unwrap_or(palette.silence);
+        palette
+    }
+}
+
+#[derive(Clone)]
 pub struct Display {
-    canvas: WindowCanvas,
-    pixels: [u64; Display::HEIGHT],
+    // Each row is a u128, the most significant bit is the leftmost pixel.
+    // In lores mode only the first 32 rows and 64 columns are used.
+    planes: [[u128; Display::MAX_HEIGHT]; Display::PLANES],
+    // Bitmask of the planes affected by drawing, clearing and scrolling
+    selected_planes: u8,
+    hires: bool,
+    color_layer: Option<Box<ColorLayer>>,
+    palette: Palette,
+    // true while the sound timer is active
+    buzzer: bool,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
+    }
 }
 
 impl Display {
-    pub const WIDTH: usize = 64;
-    pub const HEIGHT: usize = 32;
-    const FOREGROUND_COLOR: (u8, u8, u8) = (60, 163, 214);
-    const BACKGROUND_COLOR: (u8, u8, u8) = (0, 0, 0);
+    pub const LORES_WIDTH: usize = 64;
+    pub const LORES_HEIGHT: usize = 32;
+    pub const MAX_WIDTH: usize = 128;
+    pub const MAX_HEIGHT: usize = 64;
+    // XO-CHIP has two bitplanes, for four colours
+    pub const PLANES: usize = 2;
 
-    pub fn new(canvas: WindowCanvas) -> Display {
+    pub fn new() -> Display {
         Display {
-            canvas,
-            pixels: [0; Display::HEIGHT],
+            planes: [[0; Display::MAX_HEIGHT]; Display::PLANES],
+            selected_planes: 0x1,
+            hires: false,
+            color_layer: None,
+            palette: Palette::DEFAULT,
+            buzzer: false,
         }
     }
 
-    pub fn clear(&mut self) {
-        for i in 0..self.pixels.len() {
-            self.pixels[i] = 0;
+    // Only CHIP-8X programs use the colour layer
+    pub fn enable_color_layer(&mut self) {
+        if self.color_layer.is_none() {
+            self.color_layer = Some(Box::default());
         }
     }
 
-    pub fn render(&mut self) {
-        self.canvas.set_draw_color(Display::BACKGROUND_COLOR);
-        self.canvas.clear();
-
-        let window_width = self.canvas.window().size().0 as usize;
-        let window_height = self.canvas.window().size().1 as usize;
-        let block = std::cmp::min(
-            window_width / Display::WIDTH,
-            window_height / Display::HEIGHT,
-        );
-        let start_x = (window_width - Display::WIDTH * block) / 2;
-        let start_y = (window_height - Display::HEIGHT * block) / 2;
-
-        self.canvas.set_draw_color(Display::FOREGROUND_COLOR);
-        for y in 0..Display::HEIGHT {
-            for x in 0..Display::WIDTH {
-                if self.is_pixel_on(x, y) {
-                    self.canvas
-                        .fill_rect(sdl2::rect::Rect::new(
-                            (start_x + x * block) as i32,
-                            (start_y + y * block) as i32,
-                            block as u32,
-                            block as u32,
-                        ))
-                        .unwrap();
-                }
+    pub fn color_layer(&self) -> Option<&ColorLayer> {
+        self.color_layer.as_deref()
+    }
+
+    pub fn color_layer_mut(&mut self) -> Option<&mut ColorLayer> {
+        self.color_layer.as_deref_mut()
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn is_buzzer_on(&self) -> bool {
+        self.buzzer
+    }
+
+    pub fn set_buzzer(&mut self, buzzer: bool) {
+        self.buzzer = buzzer;
+    }
+
+    // Colour around the display, depends on the buzzer
+    pub fn border_color(&self) -> Rgb {
+        if self.buzzer {
+            self.palette.buzzer
+        } else {
+            self.palette.silence
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            Display::MAX_WIDTH
+        } else {
+            Display::LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            Display::MAX_HEIGHT
+        } else {
+            Display::LORES_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // Switching resolution also clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.planes = [[0; Display::MAX_HEIGHT]; Display::PLANES];
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        self.selected_planes
+    }
+
+    pub fn select_planes(&mut self, mask: u8) {
+        self.selected_planes = mask & 0x3;
+    }
+
+    pub fn is_plane_selected(&self, plane: usize) -> bool {
+        self.selected_planes & (1 << plane) != 0
+    }
+
+    // Clears the selected planes
+    pub fn clear(&mut self) {
+        for plane in 0..Display::PLANES {
+            if self.is_plane_selected(plane) {
+                self.planes[plane] = [0; Display::MAX_HEIGHT];
             }
         }
+    }
 
-        self.canvas
-            .draw_rect(sdl2::rect::Rect::new(
-                start_x as i32 - 1,
-                start_y as i32 - 1,
-                (Display::WIDTH * block + 2) as u32,
-                (Display::HEIGHT * block + 2) as u32,
-            ))
-            .unwrap();
+    // First plane, the only one used outside of XO-CHIP
+    pub fn pixels(&self) -> &[u128] {
+        self.plane(0)
+    }
 
-        self.canvas.present();
+    pub fn plane(&self, plane: usize) -> &[u128] {
+        &self.planes[plane][..self.height()]
     }
 
-    pub fn draw(&mut self, x: u8, y: u8, sprite: u8, wrap: bool) -> bool {
+    // Draws one row of a sprite on a plane, `width` bits wide (8 or 16),
+    // returns whether any pixel was turned off
+    pub fn draw(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        sprite: u16,
+        width: usize,
+        wrap: bool,
+    ) -> bool {
         let mut collision = false;
-        for i in 0..8 {
-            if sprite.checked_shr(7 - i).unwrap_or(0) & 0x1 == 0x1 {
-                let mut dx = x as usize + i as usize;
-                let mut dy = y as usize;
+        for i in 0..width {
+            if sprite.checked_shr((width - 1 - i) as u32).unwrap_or(0) & 0x1 == 0x1 {
+                let mut dx = x + i;
+                let mut dy = y;
 
                 if wrap {
-                    dx = dx % Display::WIDTH;
-                    dy = dy % Display::HEIGHT;
-                } else if dx >= Display::WIDTH || dy >= Display::HEIGHT {
+                    dx %= self.width();
+                    dy %= self.height();
+                } else if dx >= self.width() || dy >= self.height() {
                     continue;
                 }
 
-                if self.is_pixel_on(dx, dy) {
+                let mask = Display::column_mask(dx);
+                if self.planes[plane][dy] & mask != 0 {
                     collision = true;
                 }
-                self.pixels[dy] ^= 0x1_u64.checked_shl((63 - dx) as u32).unwrap_or(0);
+                self.planes[plane][dy] ^= mask;
             }
         }
         collision
     }
 
-    fn is_pixel_on(&self, x: usize, y: usize) -> bool {
-        self.pixels[y].checked_shr((63 - x) as u32).unwrap_or(0) & 1 == 1
+    pub fn is_pixel_on(&self, x: usize, y: usize) -> bool {
+        self.color(x, y) != 0
+    }
+
+    // Colour index of a pixel, bit n is set when the pixel is on in plane n
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        let mask = Display::column_mask(x);
+        (0..Display::PLANES)
+            .filter(|&plane| self.planes[plane][y] & mask != 0)
+            .fold(0, |color, plane| color | (1 << plane))
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        let height = self.height();
+        self.for_selected_planes(|rows| {
+            for y in (0..height).rev() {
+                rows[y] = if y >= n { rows[y - n] } else { 0 };
+            }
+        });
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        let height = self.height();
+        self.for_selected_planes(|rows| {
+            for y in 0..height {
+                rows[y] = if y + n < height { rows[y + n] } else { 0 };
+            }
+        });
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        let visible = self.row_mask();
+        self.for_selected_planes(|rows| {
+            for row in rows.iter_mut() {
+                *row = (*row >> n) & visible;
+            }
+        });
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.for_selected_planes(|rows| {
+            for row in rows.iter_mut() {
+                *row <<= n;
+            }
+        });
+    }
+
+    // The pixels and the modes, the palette comes from the ROM
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for row in self.planes.iter().flatten() {
+            w.u128(*row);
+        }
+        w.u8(self.selected_planes);
+        w.bool(self.hires);
+        w.option(self.color_layer.as_deref(), |w, layer| {
+            w.u8(layer.background as u8);
+            for row in &layer.cells {
+                w.bytes(row);
+            }
+        });
+    }
+
+    // Decodes into a copy, like Config::load_state
+    pub fn load_state(&self, r: &mut StateReader) -> Result<Display, SaveStateError> {
+        let mut display = self.clone();
+        for row in display.planes.iter_mut().flatten() {
+            *row = r.u128()?;
+        }
+        display.selected_planes = r.u8()?;
+        display.hires = r.bool()?;
+        display.color_layer = r.option(|r| {
+            let mut layer = ColorLayer::new();
+            layer.background = r.u8()? as usize % ColorLayer::BACKGROUNDS.len();
+            for row in layer.cells.iter_mut() {
+                *row = r.array()?;
+            }
+            Ok(Box::new(layer))
+        })?;
+        Ok(display)
+    }
+
+    fn for_selected_planes(&mut self, mut f: impl FnMut(&mut [u128; Display::MAX_HEIGHT])) {
+        for plane in 0..Display::PLANES {
+            if self.is_plane_selected(plane) {
+                f(&mut self.planes[plane]);
+            }
+        }
+    }
+
+    fn column_mask(x: usize) -> u128 {
+        0x1_u128 << (Display::MAX_WIDTH - 1 - x)
+    }
+
+    fn row_mask(&self) -> u128 {
+        !0_u128 << (Display::MAX_WIDTH - self.width())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_from_colors() {
+        let colors = Colors {
+            pixels: vec!["#996600".parse().unwrap(), "#FFCC00".parse().unwrap()],
+            buzzer: Some(Rgb(0xff, 0xaa, 0x00)),
+            silence: None,
+        };
+        let palette = Palette::from_colors(&colors);
+        assert_eq!(palette.pixels[0], Rgb(0x99, 0x66, 0x00));
+        assert_eq!(palette.pixels[1], Rgb(0xff, 0xcc, 0x00));
+        assert_eq!(palette.pixels[3], Palette::DEFAULT.pixels[3]);
+        assert!("#12345".parse::<Rgb>().is_err());
+
+        let mut display = Display::new();
+        display.set_palette(palette);
+        assert_eq!(display.border_color(), Palette::DEFAULT.silence);
+        display.set_buzzer(true);
+        assert_eq!(display.border_color(), Rgb(0xff, 0xaa, 0x00));
+    }
+
+    #[test]
+    fn draw_clips_and_wraps() {
+        let mut display = Display::new();
+        assert!(!display.draw(0, 60, 0, 0xFF, 8, false));
+        assert_eq!(display.pixels()[0], 0xF_u128 << 64);
+
+        display.clear();
+        display.draw(0, 60, 31, 0xFF, 8, true);
+        assert!(display.is_pixel_on(0, 31));
+        assert!(display.is_pixel_on(63, 31));
+        assert!(!display.is_pixel_on(4, 31));
+    }
+
+    #[test]
+    fn scroll() {
+        let mut display = Display::new();
+        display.set_hires(true);
+        display.draw(0, 0, 0, 0xFFFF, 16, false);
+
+        display.scroll_down(3);
+        assert_eq!(display.pixels()[0], 0);
+        assert!(display.is_pixel_on(0, 3));
+
+        display.scroll_right(4);
+        assert!(!display.is_pixel_on(3, 3));
+        assert!(display.is_pixel_on(19, 3));
+
+        display.scroll_left(4);
+        assert!(display.is_pixel_on(0, 3));
+        assert!(!display.is_pixel_on(16, 3));
+
+        display.scroll_up(3);
+        assert!(display.is_pixel_on(0, 0));
+    }
+
+    #[test]
+    fn lores_scroll_right_drops_pixels() {
+        let mut display = Display::new();
+        display.draw(0, 56, 0, 0xFF, 8, false);
+        display.scroll_right(4);
+        assert_eq!(display.pixels()[0], 0xF_u128 << 64);
+    }
+
+    #[test]
+    fn color_layer() {
+        let mut layer = ColorLayer::new();
+        assert_eq!(layer.background(), 2);
+        for _ in 0..4 {
+            layer.next_background();
+        }
+        assert_eq!(layer.background(), 2);
+
+        layer.set_foreground(1, 3, 0xC);
+        assert_eq!(layer.foreground(8, 3), 0x4);
+        assert_eq!(layer.foreground(7, 3), 0x1);
+        // outside of the screen
+        layer.set_foreground(8, 3, 0x5);
+    }
+
+    #[test]
+    fn planes() {
+        let mut display = Display::new();
+        display.draw(0, 0, 0, 0xC0, 8, false);
+        display.draw(1, 1, 0, 0x80, 8, false);
+        assert_eq!(display.color(0, 0), 0x1);
+        assert_eq!(display.color(1, 0), 0x3);
+        assert_eq!(display.color(2, 0), 0x0);
+
+        // only the selected planes are cleared
+        display.select_planes(0x2);
+        display.clear();
+        assert_eq!(display.color(1, 0), 0x1);
     }
 }
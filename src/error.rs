@@ -0,0 +1,91 @@
+use std::fmt;
+use std::io;
+
+use crate::assembler::AssembleError;
+use crate::savestate::SaveStateError;
+
+#[derive(Debug)]
+pub enum ChipError {
+    Io(io::Error),
+    RomTooLarge { size: usize, max: usize },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { pc: u16, address: usize },
+    InvalidOpcode { pc: u16, opcode: u16 },
+    Assembly(AssembleError),
+    SaveState(SaveStateError),
+    // No ROM loaded, or nowhere to keep the slot files
+    NoSaveSlots,
+    SaveSlot(io::Error),
+    Movie(String),
+    Trace(io::Error),
+}
+
+impl fmt::Display for ChipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChipError::Io(e) => write!(f, "Error reading ROM file: {}", e),
+            ChipError::RomTooLarge { size, max } => {
+                write!(
+                    f,
+                    "ROM is {} bytes, but at most {} fit in memory",
+                    size, max
+                )
+            }
+            ChipError::StackOverflow { pc } => {
+                write!(f, "Stack overflow on call at {:04X}", pc)
+            }
+            ChipError::StackUnderflow { pc } => {
+                write!(f, "Stack underflow on return at {:04X}", pc)
+            }
+            ChipError::MemoryOutOfBounds { pc, address } => {
+                write!(
+                    f,
+                    "Out of bounds memory access to {:04X} at {:04X}",
+                    address, pc
+                )
+            }
+            ChipError::InvalidOpcode { pc, opcode } => {
+                write!(f, "Invalid opcode {:04X} at {:04X}", opcode, pc)
+            }
+            ChipError::Assembly(e) => write!(f, "Error assembling the source, {}", e),
+            ChipError::SaveState(e) => write!(f, "Error loading the state, {}", e),
+            ChipError::NoSaveSlots => write!(
+                f,
+                "Save slots need a loaded ROM and a data directory ($XDG_DATA_HOME or $HOME)"
+            ),
+            ChipError::SaveSlot(e) => write!(f, "Error accessing the save slot file: {}", e),
+            ChipError::Movie(msg) => write!(f, "Error in the movie, {}", msg),
+            ChipError::Trace(e) => write!(f, "Error writing the trace file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ChipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChipError::Io(e) | ChipError::Trace(e) | ChipError::SaveSlot(e) => Some(e),
+            ChipError::Assembly(e) => Some(e),
+            ChipError::SaveState(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ChipError {
+    fn from(e: io::Error) -> Self {
+        ChipError::Io(e)
+    }
+}
+
+impl From<AssembleError> for ChipError {
+    fn from(e: AssembleError) -> Self {
+        ChipError::Assembly(e)
+    }
+}
+
+impl From<SaveStateError> for ChipError {
+    fn from(e: SaveStateError) -> Self {
+        ChipError::SaveState(e)
+    }
+}
@@ -0,0 +1,67 @@
+use crate::display::Display;
+use crate::keypad::KeyHints;
+
+// Events a frontend reports to the emulator. Keys are already translated to
+// their Chip8 keypad value (0x0 - 0xF).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Quit,
+    KeyDown(u8),
+    KeyUp(u8),
+    // CHIP-8X second keypad
+    SecondKeyDown(u8),
+    SecondKeyUp(u8),
+    // Change the CPU speed by the given amount of instructions per second
+    AdjustSpeed(i64),
+    // Stop and hand control to the debugger
+    Pause,
+    // Save or load the selected save state slot
+    SaveState,
+    LoadState,
+    // Select the next (1) or previous (-1) slot
+    ChangeSlot(i32),
+    // Run backwards while true
+    Rewind(bool),
+}
+
+pub trait Video {
+    fn render(&mut self, display: &Display);
+}
+
+pub trait Input {
+    // Returns the next pending event, or None when the queue is empty
+    fn poll_event(&mut self) -> Option<InputEvent>;
+
+    // Keys the loaded ROM expects for each action, frontends may bind
+    // their arrow and action keys to them
+    fn set_key_hints(&mut self, _hints: &KeyHints) {}
+}
+
+pub trait Audio {
+    fn start(&mut self);
+    fn stop(&mut self);
+
+    // XO-CHIP audio: 128 one-bit samples, played at 4000 * 2 ^ ((pitch - 64) / 48) Hz.
+    // None goes back to the default tone. Frontends that can't play patterns
+    // keep their default tone.
+    fn set_pattern(&mut self, _pattern: Option<&[u8; 16]>, _pitch: u8) {}
+}
+
+// Frontend that draws nothing, reads no input and plays no sound.
+// Useful to run the emulator in tests or batch jobs.
+pub struct Headless;
+
+impl Video for Headless {
+    fn render(&mut self, _display: &Display) {}
+}
+
+impl Input for Headless {
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        None
+    }
+}
+
+impl Audio for Headless {
+    fn start(&mut self) {}
+    fn stop(&mut self) {}
+}
@@ -1,136 +1,273 @@
-use std::ops::Shl;
-
-use sdl2::{event::Event, keyboard::Keycode, EventPump};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
 use crate::config::Config;
+use crate::frontend::{Input, InputEvent};
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
 
+#[derive(Clone, Default)]
 pub struct Keypad {
-    event_queue: EventPump,
     keys: u16,
+    // CHIP-8X second keypad, only read by ExF2 and ExF5
+    second_keys: u16,
+    last_released: Option<u8>,
 }
 
 impl Keypad {
-    pub fn new<'a>(event_queue: EventPump) -> Keypad {
+    pub fn new() -> Keypad {
         Keypad {
-            event_queue,
             keys: 0,
+            second_keys: 0,
+            last_released: None,
+        }
+    }
+
+    pub fn handle_events(&mut self, input: &mut impl Input, config: &mut Config) -> bool {
+        while let Some(event) = input.poll_event() {
+            if !self.handle_event(event, config) {
+                return false;
+            }
         }
+
+        true
     }
 
-    pub fn handle_events(&mut self, config: &mut Config) -> bool {
-        while let Some(event) = self.event_queue.poll_event() {
-            match event {
-                Event::Quit { .. } => {
-                    return false;
-                }
-                Event::KeyDown {
-                    keycode: Some(k), ..
-                } => {
-                    self.key_pressed(k, config);
-                }
-                Event::KeyUp {
-                    keycode: Some(k), ..
-                } => {
-                    self.key_released(k);
-                }
-                _ => {}
+    pub fn handle_event(&mut self, event: InputEvent, config: &mut Config) -> bool {
+        match event {
+            InputEvent::Quit => return false,
+            InputEvent::KeyDown(k) => self.key_pressed(k),
+            InputEvent::KeyUp(k) => self.key_released(k),
+            InputEvent::SecondKeyDown(k) => self.second_keys |= Keypad::mask(k),
+            InputEvent::SecondKeyUp(k) => self.second_keys &= !Keypad::mask(k),
+            InputEvent::AdjustSpeed(delta) => {
+                config.ips = config.ips.saturating_add_signed(delta).max(1);
+                println!("Speed: {} instructions per second", config.ips);
             }
+            // handled by the run loop
+            InputEvent::Pause
+            | InputEvent::SaveState
+            | InputEvent::LoadState
+            | InputEvent::ChangeSlot(_)
+            | InputEvent::Rewind(_) => {}
         }
 
         true
     }
 
+    // Bit of a key in keys, key k is bit (15 - k). Only the low nibble is
+    // used, as the VIP's keypad decoding does, so any Vx is a valid key.
+    fn mask(key: u8) -> u16 {
+        0x8000 >> (key & 0xF)
+    }
+
     pub fn is_pressed(&self, key: u8) -> bool {
-        self.keys.checked_shr((15 - key) as u32).unwrap_or(0) & 0x1 == 1
+        self.keys & Keypad::mask(key) != 0
     }
 
-    fn key_pressed(&mut self, k: Keycode, config: &mut Config) -> Option<u8> {
-        let mut matched = true;
-        match k {
-            Keycode::Up => config.rate += 1,
-            Keycode::Down => {
-                if config.rate > 1 {
-                    config.rate -= 1
-                }
-            }
-            Keycode::Right => config.ipf += 1,
-            Keycode::Left => {
-                if config.ipf > 1 {
-                    config.ipf -= 1
-                }
-            }
-            _ => {
-                matched = false;
+    pub fn is_second_pressed(&self, key: u8) -> bool {
+        self.second_keys & Keypad::mask(key) != 0
+    }
+
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    // Replaces the whole keypad state, registering a release for every key
+    // that was down before and isn't anymore
+    pub fn set_keys(&mut self, keys: u16) {
+        for key in 0..16 {
+            let mask = Keypad::mask(key);
+            if self.keys & mask != 0 && keys & mask == 0 {
+                self.last_released = Some(key);
             }
         }
+        self.keys = keys;
+    }
 
-        if matched {
-            println!(
-                "Rate: {}Hz. Instructions per frame: {}",
-                config.rate, config.ipf
-            );
-        } else if let Some(c) = self.keycode_to_u8(k) {
-            self.keys |= 0x1_u16.shl((15 - c) as u32);
-            return Some(c);
-        }
+    pub fn key_pressed(&mut self, key: u8) {
+        self.keys |= Keypad::mask(key);
+    }
+
+    pub fn key_released(&mut self, key: u8) {
+        self.keys &= !Keypad::mask(key);
+        self.last_released = Some(key & 0xF);
+    }
+
+    // Fx0A waits for a key to be pressed and released, so it only cares about
+    // releases that happen after it started waiting
+    pub fn start_waiting(&mut self) {
+        self.last_released = None;
+    }
+
+    pub fn take_released(&mut self) -> Option<u8> {
+        self.last_released.take()
+    }
 
-        None
+    // Only what Fx0A saw, the keys held down stay as the player holds them
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.option(self.last_released, StateWriter::u8);
     }
 
-    fn key_released(&mut self, k: Keycode) -> Option<u8> {
-        let released = self.keycode_to_u8(k);
-        if let Some(c) = released {
-            self.keys &= 0x0_u16.shl((15 - c) as u32);
+    pub fn load_state(&self, r: &mut StateReader) -> Result<Keypad, SaveStateError> {
+        Ok(Keypad {
+            last_released: r.option(StateReader::u8)?,
+            ..self.clone()
+        })
+    }
+}
+
+// Player actions the program database binds to Chip8 keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Player2Up,
+    Player2Down,
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::A,
+        Action::B,
+        Action::Player2Up,
+        Action::Player2Down,
+    ];
+
+    // Name used by the "keys" object of db/programs.json
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::A => "a",
+            Action::B => "b",
+            Action::Player2Up => "player2Up",
+            Action::Player2Down => "player2Down",
         }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.name() == s)
+            .ok_or(format!(
+            "Unknown action '{}', expected up, down, left, right, a, b, player2Up or player2Down",
+            s
+        ))
+    }
+}
+
+// Chip8 key the ROM expects for each action, if any
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyHints {
+    keys: [Option<u8>; Action::ALL.len()],
+}
 
-        released
-    }
-
-    pub fn block_read(&mut self, keep_running: &mut bool, config: &mut Config) -> Option<u8> {
-        while let Some(event) = self.event_queue.poll_event() {
-            match event {
-                Event::Quit { .. } => {
-                    *keep_running = false;
-                    return None;
-                }
-                Event::KeyDown {
-                    keycode: Some(k), ..
-                } => {
-                    self.key_pressed(k, config);
-                }
-                Event::KeyUp {
-                    keycode: Some(k), ..
-                } => {
-                    if let Some(key) = self.key_released(k) {
-                        return Some(key);
-                    }
-                }
-                _ => {}
+impl KeyHints {
+    // Unknown action names and out of range keys are ignored
+    pub fn from_names(names: &BTreeMap<String, u8>) -> KeyHints {
+        let mut hints = KeyHints::default();
+        for (name, &key) in names {
+            if let Ok(action) = name.parse() {
+                hints.set(action, key);
             }
         }
+        hints
+    }
 
-        None
-    }
-
-    fn keycode_to_u8(&self, k: Keycode) -> Option<u8> {
-        match k {
-            Keycode::Num1 => Some(0x1),
-            Keycode::Num2 => Some(0x2),
-            Keycode::Num3 => Some(0x3),
-            Keycode::Num4 => Some(0xC),
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::R => Some(0xD),
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::F => Some(0xE),
-            Keycode::Z => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::V => Some(0xF),
-            _ => None,
+    pub fn key(&self, action: Action) -> Option<u8> {
+        self.keys[action as usize]
+    }
+
+    pub fn set(&mut self, action: Action, key: u8) {
+        if key <= 0xF {
+            self.keys[action as usize] = Some(key);
         }
     }
+
+    // Bindings set in other win over ours
+    pub fn merge(&mut self, other: &KeyHints) {
+        for (action, key) in other.iter() {
+            self.set(action, key);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.iter().all(Option::is_none)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Action, u8)> + '_ {
+        Action::ALL
+            .into_iter()
+            .filter_map(|action| self.key(action).map(|key| (action, key)))
+    }
+}
+
+// "action=key" as given on the command line, e.g. "a=5"
+impl FromStr for KeyHints {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (action, key) = s
+            .split_once('=')
+            .ok_or(format!("Invalid key binding '{}', expected action=key", s))?;
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|&key| key <= 0xF)
+            .ok_or(format!("Invalid Chip8 key '{}', expected 0 - F", key))?;
+
+        let mut hints = KeyHints::default();
+        hints.set(action.parse()?, key);
+        Ok(hints)
+    }
+}
+
+impl fmt::Display for KeyHints {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hints: Vec<String> = self
+            .iter()
+            .map(|(action, key)| format!("{}: {:X}", action.name(), key))
+            .collect();
+        write!(f, "{}", hints.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_hints() {
+        let names = BTreeMap::from([
+            ("left".to_string(), 4),
+            ("right".to_string(), 6),
+            ("player2Up".to_string(), 12),
+            ("jump".to_string(), 1),
+        ]);
+        let mut hints = KeyHints::from_names(&names);
+        assert_eq!(hints.key(Action::Left), Some(4));
+        assert_eq!(hints.key(Action::Player2Up), Some(0xC));
+        assert_eq!(hints.key(Action::A), None);
+        assert_eq!(hints.to_string(), "left: 4, right: 6, player2Up: C");
+
+        hints.merge(&"left=f".parse().unwrap());
+        assert_eq!(hints.key(Action::Left), Some(0xF));
+        assert_eq!(hints.key(Action::Right), Some(6));
+        assert!("a=10".parse::<KeyHints>().is_err());
+        assert!("fire=5".parse::<KeyHints>().is_err());
+    }
 }
@@ -1,5 +1,21 @@
+pub mod assembler;
 pub mod chip;
 pub mod config;
+pub mod database;
+pub mod debugger;
+pub mod detect;
+pub mod disasm;
 pub mod display;
+pub mod error;
+pub mod frontend;
 pub mod keypad;
-pub mod speaker;
+pub mod movie;
+pub mod platform;
+pub mod quirktest;
+pub mod random;
+pub mod rewind;
+pub mod savestate;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod trace;
+pub mod watch;
@@ -1,35 +1,278 @@
 // Reference docs: http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
 //
 use chip8_emulator::chip::Chip;
-use sdl2::render::WindowCanvas;
-use std::io;
+use chip8_emulator::config::Config;
+use chip8_emulator::database::{self, Database};
+use chip8_emulator::debugger::{self, Debugger};
+use chip8_emulator::disasm;
+use chip8_emulator::frontend::Input;
+use chip8_emulator::keypad::KeyHints;
+use chip8_emulator::movie::Movie;
+use chip8_emulator::quirktest;
+use chip8_emulator::sdl::SdlInput;
+use chip8_emulator::trace::{self, TraceFilter, Tracer};
+use chip8_emulator::watch::Watchpoint;
+use std::fs::File;
+use std::io::BufReader;
 use std::path::Path;
+use std::str::FromStr;
 
-fn main() -> Result<(), io::Error> {
-    let mut args = std::env::args();
-    let rom_arg = args.nth(1).expect("Expected a ROM file");
+fn main() {
+    if let Err(msg) = run() {
+        println!("{}", msg);
+    }
+}
+
+fn run() -> Result<(), String> {
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("info") => {
+            args.next();
+            return info(&args.next().ok_or("Expected a ROM file")?);
+        }
+        Some("disasm") => {
+            args.next();
+            return disasm(args);
+        }
+        Some("trace-diff") => {
+            args.next();
+            let left = args.next().ok_or("Expected two trace files")?;
+            let right = args.next().ok_or("Expected two trace files")?;
+            return trace_diff(&left, &right);
+        }
+        Some("quirks") => {
+            print!("{}", quirktest::report(&quirktest::run_all()));
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let mut rom_arg = None;
+    let mut config = Config::new();
+    let mut key_bindings = KeyHints::default();
+    let mut debugger = Debugger::new();
+    let mut watchpoints = Vec::new();
+    let mut trace_path = None;
+    let mut trace_filter = TraceFilter::default();
+    let mut record_path = None;
+    let mut replay = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--unknown-opcodes" => config.unknown_opcode = parse_value(&mut args, &arg)?,
+            "--seed" => config.seed = Some(parse_value(&mut args, &arg)?),
+            "--platform" => config.force_platform(&parse_value::<String>(&mut args, &arg)?)?,
+            "--key" => key_bindings.merge(&parse_value(&mut args, &arg)?),
+            "--debug" => debugger.pause(),
+            "--break" => {
+                let address = parse_value::<String>(&mut args, &arg)?;
+                debugger.add_breakpoint(debugger::parse_address(&address)?)
+            }
+            "--watch" => watchpoints.push(parse_value::<Watchpoint>(&mut args, &arg)?),
+            "--trace" => trace_path = Some(parse_value::<String>(&mut args, &arg)?),
+            "--trace-addresses" => {
+                let range = parse_value::<String>(&mut args, &arg)?;
+                trace_filter.addresses = Some(TraceFilter::parse_addresses(&range)?);
+            }
+            "--trace-frames" => {
+                let range = parse_value::<String>(&mut args, &arg)?;
+                trace_filter.frames = Some(TraceFilter::parse_frames(&range)?);
+            }
+            "--record" => record_path = Some(parse_value::<String>(&mut args, &arg)?),
+            "--replay" => {
+                let path = parse_value::<String>(&mut args, &arg)?;
+                let movie = Movie::load(Path::new(&path)).map_err(|e| e.to_string())?;
+                replay = Some(movie);
+            }
+            _ => rom_arg = Some(arg),
+        }
+    }
+
+    let rom_arg = rom_arg.ok_or("Expected a ROM file")?;
     let rom = Path::new(&rom_arg);
 
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
+    // the ROM has to be loaded for the platform the movie was recorded on
+    if let Some(platform) = replay.as_ref().and_then(|movie| movie.platform.as_deref()) {
+        config.force_platform(platform)?;
+    }
+
+    let mut chip = Chip::with_config(config);
+    for watchpoint in watchpoints {
+        chip.add_watchpoint(watchpoint);
+    }
+    if let Some(path) = trace_path {
+        let tracer = Tracer::create(Path::new(&path), trace_filter)
+            .map_err(|e| format!("Error creating the trace file: {}", e))?;
+        chip.set_tracer(Some(tracer));
+    }
+    chip.load(rom)
+        .map_err(|e| format!("Error loading the rom: {}", e))?;
+    warn_about_overrides();
+
+    // movies are tied to the ROM, so they start once it's loaded
+    if let Some(movie) = replay {
+        chip.start_replay(movie)
+            .map_err(|e| format!("Can't replay the movie: {}", e))?;
+    } else if record_path.is_some() {
+        chip.start_recording()
+            .map_err(|e| format!("Can't record a movie: {}", e))?;
+    }
+
+    if let Some(detection) = chip.detection() {
+        println!(
+            "ROM not in the program database, looks like {} ({:.0}% confidence{})",
+            detection.platform,
+            detection.confidence * 100.0,
+            if detection.is_confident() {
+                ""
+            } else {
+                ", keeping the defaults"
+            }
+        );
+        for reason in &detection.reasons {
+            println!("  {}", reason);
+        }
+    }
+
+    let title = match chip.metadata() {
+        Some(metadata) => format!("{} - Chip8 Emulator", metadata.program.title),
+        None => "Chip8 Emulator".to_string(),
+    };
+    let (mut video, mut input, mut audio) = chip8_emulator::sdl::init(&title)?;
+
+    let mut hints = chip.metadata().map(|m| m.key_hints()).unwrap_or_default();
+    hints.merge(&key_bindings);
+    if !hints.is_empty() {
+        println!("Controls:");
+        for (action, key) in hints.iter() {
+            println!(
+                "  {:<12} {} -> {:X}",
+                SdlInput::action_key_name(action),
+                action.name(),
+                key
+            );
+        }
+    }
+    input.set_key_hints(&hints);
+
+    if let Err(e) = chip.run_with_debugger(&mut video, &mut input, &mut audio, &mut debugger) {
+        println!("Emulation stopped: {}", e);
+    }
+
+    if let (Some(path), Some(movie)) = (record_path, chip.finish_recording()) {
+        movie
+            .save(Path::new(&path))
+            .map_err(|e| format!("Error saving the movie: {}", e))?;
+        println!("Recorded {} frames to {}", movie.frames, path);
+    }
+    if chip.is_replaying() {
+        match chip.replay_result() {
+            Some(true) => println!("Replay finished, the last frame matches the recording"),
+            Some(false) => {
+                println!("Replay finished, but the last frame differs from the recording")
+            }
+            None => println!("Replay stopped before the end of the movie"),
+        }
+    }
+
+    if let Some(tracer) = chip.tracer_mut() {
+        tracer
+            .flush()
+            .map_err(|e| format!("Error writing the trace file: {}", e))?;
+    }
+
+    if chip.unknown_opcodes() > 0 {
+        println!(
+            "{} unknown opcodes were found, the ROM may need a platform that isn't emulated",
+            chip.unknown_opcodes()
+        );
+    }
+
+    Ok(())
+}
+
+// A broken overrides file doesn't stop the emulator, the database goes on
+// without it
+fn warn_about_overrides() {
+    if let Some(e) = Database::get().overrides_error() {
+        eprintln!("{}", e);
+    }
+}
+
+// Prints what the program database knows about a ROM
+fn info(rom_arg: &str) -> Result<(), String> {
+    let rom = std::fs::read(rom_arg).map_err(|e| format!("Error reading the rom: {}", e))?;
+    let hash = database::sha1(&rom);
+
+    warn_about_overrides();
+    match Database::get().find(&hash) {
+        Some(metadata) => println!("{}", metadata),
+        None => println!(
+            "{} (SHA-1 {}) is not in the program database",
+            rom_arg, hash
+        ),
+    }
 
-    let window = video_subsystem
-        .window("Chip8 Emulator", 800, 600)
-        .position_centered()
-        .resizable()
-        .build()
-        .unwrap();
+    Ok(())
+}
 
-    let canvas: WindowCanvas = window.into_canvas().build().unwrap();
-    let event_queue = sdl_context.event_pump().unwrap();
+// Prints the ROM as Octo assembly, for the platform it would run on
+fn disasm(mut args: impl Iterator<Item = String>) -> Result<(), String> {
+    let mut rom_arg = None;
+    let mut config = Config::new();
 
-    let mut chip = Chip::new(canvas, event_queue, audio_subsystem);
-    if let Err(msg) = chip.load(rom) {
-        println!("Error loading the rom: {}", msg);
-        return Ok(());
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" => config.force_platform(&parse_value::<String>(&mut args, &arg)?)?,
+            _ => rom_arg = Some(arg),
+        }
     }
-    chip.run()?;
 
+    let rom_arg = rom_arg.ok_or("Expected a ROM file")?;
+    let rom = std::fs::read(&rom_arg).map_err(|e| format!("Error reading the rom: {}", e))?;
+
+    // loading applies the platform from the database, or the guessed one
+    let mut chip = Chip::with_config(config);
+    chip.load_rom(&rom)
+        .map_err(|e| format!("Error loading the rom: {}", e))?;
+    warn_about_overrides();
+
+    let set = chip.config().instruction_set;
+    print!("{}", disasm::disassemble(&rom, chip.program_start(), set));
+    Ok(())
+}
+
+// Reports the first cycle two instruction traces disagree on
+fn trace_diff(left: &str, right: &str) -> Result<(), String> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("Error reading {}: {}", path, e))
+    };
+
+    let divergence = trace::diff(open(left)?, open(right)?)
+        .map_err(|e| format!("Error reading the traces: {}", e))?;
+    match divergence {
+        None => println!("The traces are identical"),
+        Some(divergence) => {
+            println!("The traces diverge at cycle {}", divergence.cycle);
+            let end = "(end of trace)".to_string();
+            println!("< {}", divergence.left.unwrap_or(end.clone()));
+            println!("> {}", divergence.right.unwrap_or(end));
+        }
+    }
     Ok(())
 }
+
+fn parse_value<T>(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = args
+        .next()
+        .ok_or(format!("Expected a value after {}", flag))?;
+    value
+        .parse()
+        .map_err(|e| format!("Invalid value for {}: {}", flag, e))
+}
@@ -0,0 +1,299 @@
+// Input movies: the keypad changes of a session with the frame they happened
+// on, plus everything else the emulation depends on (the ROM, the config and
+// the random seed). Replaying one reproduces the session exactly, which the
+// hash of the last frame checks. Movies are JSON files:
+//
+// {
+//   "version": 1,
+//   "romSha1": "...",
+//   "platform": "superchip",
+//   "ips": 1800,
+//   "quirks": { "shift": true, ... },
+//   "seed": 1234,
+//   "frames": 600,
+//   "finalHash": "...",
+//   "events": [[12, { "keyDown": 5 }], [20, { "keyUp": 5 }]]
+// }
+
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::config::Config;
+use crate::display::Display;
+use crate::error::ChipError;
+use crate::frontend::InputEvent;
+use crate::platform::Quirks;
+
+// Inputs that change what the ROM sees
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum MovieInput {
+    KeyDown(u8),
+    KeyUp(u8),
+    SecondKeyDown(u8),
+    SecondKeyUp(u8),
+    AdjustSpeed(i64),
+}
+
+impl MovieInput {
+    pub fn from_event(event: InputEvent) -> Option<MovieInput> {
+        match event {
+            InputEvent::KeyDown(key) => Some(MovieInput::KeyDown(key)),
+            InputEvent::KeyUp(key) => Some(MovieInput::KeyUp(key)),
+            InputEvent::SecondKeyDown(key) => Some(MovieInput::SecondKeyDown(key)),
+            InputEvent::SecondKeyUp(key) => Some(MovieInput::SecondKeyUp(key)),
+            InputEvent::AdjustSpeed(delta) => Some(MovieInput::AdjustSpeed(delta)),
+            _ => None,
+        }
+    }
+
+    pub fn event(self) -> InputEvent {
+        match self {
+            MovieInput::KeyDown(key) => InputEvent::KeyDown(key),
+            MovieInput::KeyUp(key) => InputEvent::KeyUp(key),
+            MovieInput::SecondKeyDown(key) => InputEvent::SecondKeyDown(key),
+            MovieInput::SecondKeyUp(key) => InputEvent::SecondKeyUp(key),
+            MovieInput::AdjustSpeed(delta) => InputEvent::AdjustSpeed(delta),
+        }
+    }
+
+    pub fn key(self) -> Option<u8> {
+        match self {
+            MovieInput::KeyDown(key)
+            | MovieInput::KeyUp(key)
+            | MovieInput::SecondKeyDown(key)
+            | MovieInput::SecondKeyUp(key) => Some(key),
+            MovieInput::AdjustSpeed(_) => None,
+        }
+    }
+}
+
+// An input and the frame it's applied before
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MovieEvent(pub u64, pub MovieInput);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Movie {
+    pub version: u32,
+    pub rom_sha1: String,
+    pub platform: Option<String>,
+    pub ips: u64,
+    pub quirks: Quirks,
+    pub seed: u64,
+    // frames the session lasted and the hash of the display after the last one
+    pub frames: u64,
+    pub final_hash: String,
+    pub events: Vec<MovieEvent>,
+}
+
+impl Movie {
+    pub const VERSION: u32 = 1;
+
+    // An empty movie for a session starting with this config
+    pub fn new(rom_sha1: &str, config: &Config, seed: u64) -> Movie {
+        Movie {
+            version: Movie::VERSION,
+            rom_sha1: rom_sha1.to_string(),
+            platform: config.platform.clone(),
+            ips: config.ips,
+            quirks: config.quirks(),
+            seed,
+            frames: 0,
+            final_hash: String::new(),
+            events: Vec::new(),
+        }
+    }
+
+    // Movies come from anywhere, keys past 0xF are rejected before they
+    // reach the keypad
+    pub fn load(path: &Path) -> Result<Movie, ChipError> {
+        let json = fs::read_to_string(path).map_err(|e| ChipError::Movie(e.to_string()))?;
+        Movie::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Movie, ChipError> {
+        let movie: Movie =
+            serde_json::from_str(json).map_err(|e| ChipError::Movie(e.to_string()))?;
+        if movie.version != Movie::VERSION {
+            return Err(ChipError::Movie(format!(
+                "movie version {} isn't supported, expected {}",
+                movie.version,
+                Movie::VERSION
+            )));
+        }
+        for &MovieEvent(frame, input) in &movie.events {
+            if let Some(key) = input.key().filter(|&key| key > 0xF) {
+                return Err(ChipError::Movie(format!(
+                    "invalid key {:#X} on frame {}",
+                    key, frame
+                )));
+            }
+        }
+        Ok(movie)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(path, json).map_err(|e| e.to_string())
+    }
+}
+
+// A movie being recorded or replayed by the chip
+pub enum MovieState {
+    Recording(Movie),
+    // next is the index of the first event not applied yet
+    Replaying { movie: Movie, next: usize },
+}
+
+impl MovieState {
+    pub fn is_replaying(&self) -> bool {
+        matches!(self, MovieState::Replaying { .. })
+    }
+
+    pub fn record(&mut self, frame: u64, input: MovieInput) {
+        if let MovieState::Recording(movie) = self {
+            movie.events.push(MovieEvent(frame, input));
+        }
+    }
+
+    // The recorded inputs to apply before the frame runs
+    pub fn inputs_for(&mut self, frame: u64) -> Vec<MovieInput> {
+        let MovieState::Replaying { movie, next } = self else {
+            return Vec::new();
+        };
+
+        let mut inputs = Vec::new();
+        while let Some(&MovieEvent(at, input)) = movie.events.get(*next) {
+            if at > frame {
+                break;
+            }
+            inputs.push(input);
+            *next += 1;
+        }
+        inputs
+    }
+}
+
+// Hash of the pixels on screen, compared at the end of a replay
+pub fn frame_hash(display: &Display) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update((display.width() as u32).to_be_bytes());
+    hasher.update((display.height() as u32).to_be_bytes());
+    for plane in 0..Display::PLANES {
+        for row in display.plane(plane) {
+            hasher.update(row.to_be_bytes());
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::Chip;
+
+    // loop: v0 := random 0xFF; v1 := key; i := 0x200; sprite v0 v1 5; jump loop
+    const ROM: [u8; 10] = [0xC0, 0xFF, 0xF1, 0x0A, 0xA2, 0x00, 0xD0, 0x15, 0x12, 0x00];
+
+    fn record() -> (Movie, Display) {
+        let mut chip = Chip::new();
+        chip.load_rom(&ROM).unwrap();
+        chip.start_recording().unwrap();
+        for frame in 0..120 {
+            match frame % 10 {
+                2 => chip.handle_input(InputEvent::KeyDown((frame % 16) as u8)),
+                5 => chip.handle_input(InputEvent::KeyUp((frame % 16) as u8)),
+                _ => {}
+            }
+            chip.next_frame().unwrap();
+        }
+        (chip.finish_recording().unwrap(), chip.display().clone())
+    }
+
+    fn replay(movie: Movie) -> (Option<bool>, Display) {
+        let mut chip = Chip::new();
+        chip.load_rom(&ROM).unwrap();
+        chip.start_replay(movie).unwrap();
+        // the player's keys are ignored
+        chip.handle_input(InputEvent::KeyDown(1));
+        while chip.replay_result().is_none() {
+            chip.next_frame().unwrap();
+        }
+        (chip.replay_result(), chip.display().clone())
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let (movie, display) = record();
+        assert_eq!(movie.frames, 120);
+        assert_eq!(movie.events.len(), 24);
+        assert_eq!(movie.events[0], MovieEvent(2, MovieInput::KeyDown(2)));
+
+        let json = serde_json::to_string(&movie).unwrap();
+        assert!(json.contains("[2,{\"keyDown\":2}]"));
+        let movie: Movie = serde_json::from_str(&json).unwrap();
+
+        let (result, replayed) = replay(movie.clone());
+        assert_eq!(result, Some(true));
+        assert_eq!(replayed.pixels(), display.pixels());
+
+        let mut other_seed = movie;
+        other_seed.seed += 1;
+        assert_eq!(replay(other_seed).0, Some(false));
+    }
+
+    #[test]
+    fn keys_past_f_are_rejected() {
+        let (movie, _) = record();
+        let json = serde_json::to_string(&movie).unwrap();
+        assert!(Movie::from_json(&json).is_ok());
+
+        let bad = json.replace("[2,{\"keyDown\":2}]", "[2,{\"keyDown\":16}]");
+        assert_ne!(bad, json);
+        let error = Movie::from_json(&bad).unwrap_err();
+        assert!(matches!(error, ChipError::Movie(_)));
+        assert_eq!(
+            error.to_string(),
+            "Error in the movie, invalid key 0x10 on frame 2"
+        );
+    }
+
+    // The order main uses: the ROM file is loaded, then the movie starts
+    #[test]
+    fn record_and_replay_files() {
+        let dir = std::env::temp_dir().join(format!("chip8-movie-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("rom.ch8");
+        let movie_path = dir.join("movie.json");
+        fs::write(&rom_path, ROM).unwrap();
+
+        let mut chip = Chip::new();
+        assert!(chip.start_recording().is_err());
+        chip.load(&rom_path).unwrap();
+        chip.start_recording().unwrap();
+        chip.handle_input(InputEvent::KeyDown(7));
+        for _ in 0..30 {
+            chip.next_frame().unwrap();
+        }
+        chip.finish_recording().unwrap().save(&movie_path).unwrap();
+
+        let movie = Movie::load(&movie_path).unwrap();
+        let mut config = Config::new();
+        if let Some(platform) = movie.platform.as_deref() {
+            config.force_platform(platform).unwrap();
+        }
+        let mut chip = Chip::with_config(config);
+        chip.load(&rom_path).unwrap();
+        chip.start_replay(movie).unwrap();
+        while chip.replay_result().is_none() {
+            chip.next_frame().unwrap();
+        }
+        assert_eq!(chip.replay_result(), Some(true));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
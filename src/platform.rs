@@ -0,0 +1,190 @@
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::config::InstructionSet;
+
+// A platform profile from db/platforms.json
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub display_resolutions: Vec<Resolution>,
+    // instructions per 60Hz frame
+    pub default_tickrate: u64,
+    pub quirks: Quirks,
+}
+
+// Quirks missing from a profile take their default value from db/quirks.json
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Quirks {
+    pub shift: bool,
+    pub memory_increment_by_x: bool,
+    pub memory_leave_i_unchanged: bool,
+    pub wrap: bool,
+    pub jump: bool,
+    pub vblank: bool,
+    pub logic: bool,
+}
+
+// A quirk described in db/quirks.json
+#[derive(Debug, Clone, Deserialize)]
+struct QuirkInfo {
+    id: String,
+    default: bool,
+}
+
+static QUIRK_DEFAULTS: OnceLock<Quirks> = OnceLock::new();
+
+impl Default for Quirks {
+    fn default() -> Self {
+        *QUIRK_DEFAULTS.get_or_init(|| {
+            let infos: Vec<QuirkInfo> = serde_json::from_str(include_str!("../db/quirks.json"))
+                .expect("db/quirks.json is invalid");
+            let default = |name: &str| {
+                infos
+                    .iter()
+                    .find(|info| info.id == name)
+                    .is_some_and(|info| info.default)
+            };
+            Quirks {
+                shift: default("shift"),
+                memory_increment_by_x: default("memoryIncrementByX"),
+                memory_leave_i_unchanged: default("memoryLeaveIUnchanged"),
+                wrap: default("wrap"),
+                jump: default("jump"),
+                vblank: default("vblank"),
+                logic: default("logic"),
+            }
+        })
+    }
+}
+
+impl Quirks {
+    // Their names in db/platforms.json, in the order of values
+    pub const NAMES: [&'static str; 7] = [
+        "shift",
+        "memoryIncrementByX",
+        "memoryLeaveIUnchanged",
+        "wrap",
+        "jump",
+        "vblank",
+        "logic",
+    ];
+
+    pub fn values(&self) -> [bool; 7] {
+        [
+            self.shift,
+            self.memory_increment_by_x,
+            self.memory_leave_i_unchanged,
+            self.wrap,
+            self.jump,
+            self.vblank,
+            self.logic,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl<'de> Deserialize<'de> for Resolution {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let parsed = text
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)));
+
+        match parsed {
+            Some((width, height)) => Ok(Resolution { width, height }),
+            None => Err(serde::de::Error::custom(format!(
+                "invalid resolution '{}'",
+                text
+            ))),
+        }
+    }
+}
+
+static PLATFORMS: OnceLock<Vec<Platform>> = OnceLock::new();
+
+impl Platform {
+    // Every profile in db/platforms.json, which is compiled into the binary
+    pub fn all() -> &'static [Platform] {
+        PLATFORMS.get_or_init(|| {
+            serde_json::from_str(include_str!("../db/platforms.json"))
+                .expect("db/platforms.json is invalid")
+        })
+    }
+
+    pub fn find(id: &str) -> Option<&'static Platform> {
+        Platform::all().iter().find(|platform| platform.id == id)
+    }
+
+    // The SUPER-CHIP hires mode, 00FF is only honoured on profiles listing it
+    pub const HIRES: Resolution = Resolution {
+        width: 128,
+        height: 64,
+    };
+
+    pub fn has_resolution(&self, resolution: Resolution) -> bool {
+        self.display_resolutions.contains(&resolution)
+    }
+
+    // Opcodes we emulate for this platform. MEGA-CHIP only gets its
+    // SUPER-CHIP subset, the rest of the profile still applies.
+    pub fn instruction_set(&self) -> InstructionSet {
+        match self.id.as_str() {
+            "superchip1" | "superchip" | "megachip8" => InstructionSet::SuperChip,
+            "xochip" => InstructionSet::XoChip,
+            "chip8x" => InstructionSet::Chip8X,
+            _ => InstructionSet::Chip8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_every_profile() {
+        let ids: Vec<&str> = Platform::all().iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(
+            ids,
+            [
+                "originalChip8",
+                "hybridVIP",
+                "modernChip8",
+                "chip8x",
+                "chip48",
+                "superchip1",
+                "superchip",
+                "megachip8",
+                "xochip"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_quirks_take_the_defaults() {
+        assert_eq!(Quirks::default().values(), [false; 7]);
+        let superchip = Platform::find("superchip").unwrap();
+        assert_eq!(
+            superchip.quirks.memory_increment_by_x,
+            Quirks::default().memory_increment_by_x
+        );
+        assert!(superchip.quirks.memory_leave_i_unchanged);
+        assert_eq!(superchip.default_tickrate, 30);
+        assert!(superchip.has_resolution(Platform::HIRES));
+        assert!(!Platform::find("chip48")
+            .unwrap()
+            .has_resolution(Platform::HIRES));
+    }
+}
@@ -0,0 +1,296 @@
+// Checks the quirks of every platform profile with Timendus' quirks test
+// (tests_roms/5-quirks.ch8). The ROM is run without a window, the tests for
+// the platform's instruction set are picked from the menu and the results it
+// draws are read back from the screen:
+//
+//   VF RESET   ON  ✓
+//   MEMORY     ON  ✓
+//   DISP.WAIT  ON  ✓
+//   CLIPPING   ON  ✓
+//   SHIFTING   OFF ✓
+//   JUMPING    OFF ✓
+//
+// ON or OFF is what the ROM found, the SUPER-CHIP and XO-CHIP tests say BOTH
+// or NONE for the resolutions clipping and the display wait apply in. The
+// marks compare it with the platform the menu entry stands for and are only
+// used to tell the two ways I can be incremented apart.
+
+use std::fmt::Write as _;
+
+use crate::chip::Chip;
+use crate::config::{Config, InstructionSet};
+use crate::display::Display;
+use crate::frontend::InputEvent;
+use crate::platform::{Platform, Quirks};
+
+pub const ROM: &[u8] = include_bytes!("../tests_roms/5-quirks.ch8");
+
+// The menu shows up within a second. Each key picks a menu entry, the next
+// one is pressed once the previous menu is gone.
+const MENU_FRAME: u64 = 60;
+const MENU_SPACING: u64 = 20;
+const PRESS_FRAMES: u64 = 3;
+// The display wait test is the slowest, all of them are done well before
+const TIMEOUT_FRAMES: u64 = 3600;
+
+// Where the results are drawn: one row of 4 pixel high glyphs every 5 lines,
+// the value then the mark
+const FIRST_ROW: usize = 1;
+const ROW_SPACING: usize = 5;
+const VALUE_X: usize = 42;
+const MARK_X: usize = 59;
+
+const ON: [&str; 4] = [
+    "### ##         ",
+    "# # # #        ",
+    "# # # #        ",
+    "### # #        ",
+];
+const OFF: [&str; 4] = [
+    "### ### ###    ",
+    "# # #   #      ",
+    "# # ##  ##     ",
+    "### #   #      ",
+];
+const BOTH: [&str; 4] = [
+    "##  ### ### # #",
+    "### # #  #  ###",
+    "# # # #  #  # #",
+    "### ###  #  # #",
+];
+const NONE: [&str; 4] = [
+    "##  ### ##  ###",
+    "# # # # # # ## ",
+    "# # # # # # #  ",
+    "# # ### # # ###",
+];
+const CHECK: [&str; 4] = ["   ", "# #", "## ", "#  "];
+const CROSS: [&str; 4] = ["   ", "# #", " # ", "# #"];
+
+// Result rows in the order they're drawn
+const VF_RESET: usize = 0;
+const MEMORY: usize = 1;
+const DISPLAY_WAIT: usize = 2;
+const CLIPPING: usize = 3;
+const SHIFTING: usize = 4;
+const JUMPING: usize = 5;
+const ROWS: usize = 6;
+
+// What the ROM found on one platform, or why it couldn't run there
+pub struct QuirkResult {
+    pub platform: &'static Platform,
+    pub found: Result<Quirks, String>,
+}
+
+impl QuirkResult {
+    // Names of the quirks that don't match the profile
+    pub fn differences(&self) -> Vec<&'static str> {
+        let Ok(found) = &self.found else {
+            return Vec::new();
+        };
+        let expected = self.platform.quirks.values();
+        Quirks::NAMES
+            .iter()
+            .zip(found.values().iter().zip(expected))
+            .filter(|(_, (found, expected))| *found != expected)
+            .map(|(&name, _)| name)
+            .collect()
+    }
+}
+
+pub fn run_all() -> Vec<QuirkResult> {
+    Platform::all()
+        .iter()
+        .map(|platform| QuirkResult {
+            platform,
+            found: run(platform),
+        })
+        .collect()
+}
+
+// The menu entries testing the platform's instruction set: 1 CHIP-8, 2 then
+// 1 or 2 for the modern or legacy SUPER-CHIP, 3 XO-CHIP
+fn menu_keys(platform: &Platform) -> Result<&'static [u8], String> {
+    match platform.instruction_set() {
+        InstructionSet::Chip8 => Ok(&[1]),
+        InstructionSet::SuperChip if platform.id == "superchip1" => Ok(&[2, 2]),
+        InstructionSet::SuperChip => Ok(&[2, 1]),
+        InstructionSet::XoChip => Ok(&[3]),
+        InstructionSet::Chip8X => {
+            Err("CHIP-8X programs start at 0x300, the ROM needs 0x200".to_string())
+        }
+    }
+}
+
+// Runs the ROM until all the results are on screen
+pub fn run(platform: &Platform) -> Result<Quirks, String> {
+    let keys = menu_keys(platform)?;
+    let mut config = Config::new();
+    config.seed = Some(0);
+    config.force_platform(&platform.id)?;
+    let mut chip = Chip::with_config(config);
+    chip.load_rom(ROM).map_err(|e| e.to_string())?;
+
+    for frame in 0..TIMEOUT_FRAMES {
+        for (i, &key) in keys.iter().enumerate() {
+            let pressed = MENU_FRAME + i as u64 * MENU_SPACING;
+            if frame == pressed {
+                chip.handle_input(InputEvent::KeyDown(key));
+            } else if frame == pressed + PRESS_FRAMES {
+                chip.handle_input(InputEvent::KeyUp(key));
+            }
+        }
+        chip.next_frame().map_err(|e| e.to_string())?;
+
+        if let Some(quirks) = read_results(chip.display()) {
+            return Ok(quirks);
+        }
+    }
+    Err(format!("no results after {} frames", TIMEOUT_FRAMES))
+}
+
+// None until every row shows a value and a mark
+pub fn read_results(display: &Display) -> Option<Quirks> {
+    let mut on = [false; ROWS];
+    let mut checked = [false; ROWS];
+    for row in 0..ROWS {
+        let y = FIRST_ROW + row * ROW_SPACING;
+        let values = [(ON, true), (OFF, false), (BOTH, true), (NONE, false)];
+        on[row] = read_glyph(display, VALUE_X, y, &values)?;
+        checked[row] = read_glyph(display, MARK_X, y, &[(CHECK, true), (CROSS, false)])?;
+    }
+
+    // I left unchanged shows OFF. When it moved but not by X + 1 the test
+    // fails, which is SUPER-CHIP 1.0's I += X.
+    Some(Quirks {
+        shift: on[SHIFTING],
+        memory_increment_by_x: on[MEMORY] && !checked[MEMORY],
+        memory_leave_i_unchanged: !on[MEMORY],
+        wrap: !on[CLIPPING],
+        jump: on[JUMPING],
+        vblank: on[DISPLAY_WAIT],
+        logic: on[VF_RESET],
+    })
+}
+
+fn read_glyph<T: Copy>(
+    display: &Display,
+    x: usize,
+    y: usize,
+    glyphs: &[([&str; 4], T)],
+) -> Option<T> {
+    glyphs
+        .iter()
+        .find(|(glyph, _)| {
+            glyph.iter().enumerate().all(|(dy, line)| {
+                line.chars()
+                    .enumerate()
+                    .all(|(dx, c)| (c == '#') == display.is_pixel_on(x + dx, y + dy))
+            })
+        })
+        .map(|&(_, value)| value)
+}
+
+// Platforms against quirks, differences with db/platforms.json are starred
+// and listed below the table
+pub fn report(results: &[QuirkResult]) -> String {
+    let platform_width = results
+        .iter()
+        .map(|result| result.platform.id.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    let _ = write!(out, "{:width$}", "", width = platform_width);
+    for name in Quirks::NAMES {
+        let _ = write!(out, "  {}", name);
+    }
+    out.push('\n');
+
+    for result in results {
+        let _ = write!(out, "{:width$}", result.platform.id, width = platform_width);
+        match &result.found {
+            Ok(found) => {
+                let expected = result.platform.quirks.values();
+                for ((name, found), expected) in
+                    Quirks::NAMES.iter().zip(found.values()).zip(expected)
+                {
+                    let value = format!(
+                        "{}{}",
+                        if found { "on" } else { "off" },
+                        if found == expected { "" } else { "*" }
+                    );
+                    let _ = write!(out, "  {:width$}", value, width = name.len());
+                }
+            }
+            Err(e) => {
+                let _ = write!(out, "  can't run the test: {}", e);
+            }
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+    }
+
+    let differences: Vec<String> = results
+        .iter()
+        .flat_map(|result| {
+            result.differences().into_iter().map(|name| {
+                format!(
+                    "{}: {} doesn't match db/platforms.json",
+                    result.platform.id, name
+                )
+            })
+        })
+        .collect();
+    if differences.is_empty() {
+        out.push_str("\nEvery quirk tested matches db/platforms.json\n");
+    } else {
+        out.push('\n');
+        for difference in differences {
+            let _ = writeln!(out, "{}", difference);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quirks_match_the_profiles() {
+        let results = run_all();
+        assert_eq!(results.len(), Platform::all().len());
+        for result in &results {
+            if result.platform.id == "chip8x" {
+                assert!(result.found.is_err());
+                continue;
+            }
+            assert_eq!(result.found.as_ref(), Ok(&result.platform.quirks));
+        }
+
+        let report = report(&results);
+        assert!(report.starts_with(&format!("{:13}  shift  memoryIncrementByX", "")));
+        assert!(report.contains("\nsuperchip1     on     on                  off"));
+        assert!(report.contains(
+            "\nchip8x         can't run the test: CHIP-8X programs start at 0x300, the ROM needs 0x200\n"
+        ));
+        assert!(report.ends_with("Every quirk tested matches db/platforms.json\n"));
+    }
+
+    #[test]
+    fn differences_are_starred() {
+        let platform = Platform::find("modernChip8").unwrap();
+        let mut found = platform.quirks;
+        found.vblank = true;
+        let results = [QuirkResult {
+            platform,
+            found: Ok(found),
+        }];
+
+        assert_eq!(results[0].differences(), ["vblank"]);
+        let report = report(&results);
+        assert!(report.contains("  on*     off"));
+        assert!(report.ends_with("modernChip8: vblank doesn't match db/platforms.json\n"));
+    }
+}
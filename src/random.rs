@@ -0,0 +1,63 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::savestate::{SaveStateError, StateReader, StateWriter};
+
+// Source of the random bytes used by Cxkk, a ChaCha8 generator seedable for
+// reproducible runs
+pub struct Random(Box<ChaCha8Rng>);
+
+impl Random {
+    pub fn new(seed: Option<u64>) -> Random {
+        Random(Box::new(match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        }))
+    }
+
+    pub fn next_byte(&mut self) -> u8 {
+        self.0.gen()
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.0.get_seed());
+        w.u64(self.0.get_stream());
+        w.u128(self.0.get_word_pos());
+    }
+
+    // Carries on with the same sequence of bytes the saved generator had left
+    pub fn load_state(r: &mut StateReader) -> Result<Random, SaveStateError> {
+        let mut rng = ChaCha8Rng::from_seed(r.array()?);
+        rng.set_stream(r.u64()?);
+        rng.set_word_pos(r.u128()?);
+        Ok(Random(Box::new(rng)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded_is_deterministic() {
+        let mut a = Random::new(Some(42));
+        let mut b = Random::new(Some(42));
+        let a: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        let b: Vec<u8> = (0..32).map(|_| b.next_byte()).collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn saved_state_continues_the_sequence() {
+        let mut rng = Random::new(None);
+        rng.next_byte();
+        let mut w = StateWriter::new();
+        rng.save_state(&mut w);
+        let bytes = w.into_bytes();
+
+        let mut restored = Random::load_state(&mut StateReader::new(&bytes)).unwrap();
+        let a: Vec<u8> = (0..32).map(|_| rng.next_byte()).collect();
+        let b: Vec<u8> = (0..32).map(|_| restored.next_byte()).collect();
+        assert_eq!(a, b);
+    }
+}
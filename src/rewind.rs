@@ -0,0 +1,175 @@
+// History of save states for rewinding, one per frame. Only the newest state
+// is kept whole, every older one is stored as its difference with the state
+// that followed it: the two states XORed together, with the runs of zeros
+// (the bytes that didn't change) squeezed out.
+
+use std::collections::VecDeque;
+
+pub struct Rewind {
+    // the deltas lead back from this one
+    latest: Option<Vec<u8>>,
+    // oldest first
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Rewind {
+    // Frames of history kept by default, a minute at 60Hz
+    pub const DEFAULT_FRAMES: usize = 3600;
+
+    pub fn new(capacity: usize) -> Rewind {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    // Adds the state of a new frame, forgetting the oldest one when full
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(previous) = self.latest.replace(state) {
+            let latest = self.latest.as_deref().unwrap_or_default();
+            self.deltas.push_back(encode_delta(latest, &previous));
+            if self.deltas.len() > self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+    }
+
+    // Steps one frame back and returns the state there, None once the
+    // history has run out
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let latest = self.latest.as_deref()?;
+        let previous = apply_delta(latest, &delta);
+        self.latest = Some(previous.clone());
+        Some(previous)
+    }
+
+    // Frames that can be stepped back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    // Bytes used by the history
+    pub fn size(&self) -> usize {
+        self.latest.as_ref().map_or(0, Vec::len) + self.deltas.iter().map(Vec::len).sum::<usize>()
+    }
+}
+
+// The length of the old state, then pairs of the number of unchanged bytes
+// and a run of changed ones (its length and the XORed bytes)
+fn encode_delta(new: &[u8], old: &[u8]) -> Vec<u8> {
+    let len = new.len().max(old.len());
+    let xor = |i: usize| new.get(i).unwrap_or(&0) ^ old.get(i).unwrap_or(&0);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, old.len());
+    let mut i = 0;
+    while i < len {
+        let unchanged_start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        let changed_start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, changed_start - unchanged_start);
+        write_varint(&mut delta, i - changed_start);
+        delta.extend((changed_start..i).map(xor));
+    }
+    delta
+}
+
+fn apply_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut bytes = delta.iter().copied();
+    let old_len = read_varint(&mut bytes);
+    let mut old = new.to_vec();
+    old.resize(new.len().max(old_len), 0);
+
+    let mut i = 0;
+    while bytes.len() > 0 {
+        i += read_varint(&mut bytes);
+        let changed = read_varint(&mut bytes);
+        for (byte, xor) in old[i..i + changed].iter_mut().zip(&mut bytes) {
+            *byte ^= xor;
+        }
+        i += changed;
+    }
+    old.truncate(old_len);
+    old
+}
+
+// LEB128, 7 bits per byte with the high bit set on all but the last
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut impl Iterator<Item = u8>) -> usize {
+    let mut value = 0;
+    for (shift, byte) in bytes.enumerate() {
+        value |= ((byte & 0x7F) as usize) << (7 * shift);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::Chip;
+
+    #[test]
+    fn deltas() {
+        let old = vec![1, 2, 3, 4, 5, 6, 7, 8];
+        for new in [vec![1, 2, 0, 4, 5, 6, 7, 9], vec![1, 2], vec![0; 300]] {
+            let delta = encode_delta(&new, &old);
+            assert_eq!(apply_delta(&new, &delta), old);
+        }
+        assert_eq!(encode_delta(&old, &old), [8, 8, 0]);
+    }
+
+    #[test]
+    fn rewind_frames() {
+        let mut chip = Chip::new();
+        chip.load_rom(include_bytes!("../roms/blinky.ch8")).unwrap();
+        let mut rewind = Rewind::new(Rewind::DEFAULT_FRAMES);
+
+        let mut states = Vec::new();
+        for _ in 0..1800 {
+            chip.step_frame(0).unwrap();
+            states.push(chip.save_state());
+            rewind.push(chip.save_state());
+        }
+        // 30 seconds take far less than the 1800 states themselves
+        assert!(rewind.size() * 10 < states.len() * states[0].len());
+
+        states.pop();
+        for _ in 0..600 {
+            let state = rewind.pop().unwrap();
+            assert_eq!(state, states.pop().unwrap());
+        }
+        chip.load_state(&states[states.len() - 1]).unwrap();
+        assert_eq!(chip.frame_count() as usize, states.len());
+
+        let mut short = Rewind::new(2);
+        for state in states.iter().take(5) {
+            short.push(state.clone());
+        }
+        assert_eq!(short.len(), 2);
+        assert_eq!(short.pop().as_ref(), states.get(3));
+        assert_eq!(short.pop().as_ref(), states.get(2));
+        assert_eq!(short.pop(), None);
+    }
+}
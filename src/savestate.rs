@@ -0,0 +1,293 @@
+// Save state files. Each part of the machine writes its own fields through a
+// StateWriter and reads them back in the same order with a StateReader.
+//
+// magic "CHIP8SAV", format version (u16), SHA-1 of the ROM (length prefixed
+// hex), payload length (u32), payload, then the SHA-1 of everything before it.
+// Numbers are big endian.
+
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use sha1::{Digest, Sha1};
+
+const MAGIC: &[u8; 8] = b"CHIP8SAV";
+// Bumped whenever the payload changes, older states are refused
+pub const VERSION: u16 = 1;
+const CHECKSUM_SIZE: usize = 20;
+// Slots the hotkeys cycle through
+pub const SLOTS: u8 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    NotASaveState,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    WrongRom { expected: String, found: String },
+    Truncated,
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {} isn't supported, expected {}",
+                version, VERSION
+            ),
+            SaveStateError::ChecksumMismatch => write!(f, "the save state is corrupted"),
+            SaveStateError::WrongRom { expected, found } => write!(
+                f,
+                "the save state is for the ROM with SHA-1 {}, not {}",
+                found, expected
+            ),
+            SaveStateError::Truncated => write!(f, "the save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "invalid {} in the save state", what),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+#[derive(Default)]
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter::default()
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u128(&mut self, value: u128) {
+        self.bytes.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    // Fixed size data, the reader must know the length
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes(value.as_bytes());
+    }
+
+    pub fn option<T>(&mut self, value: Option<T>, write: impl FnOnce(&mut StateWriter, T)) {
+        self.bool(value.is_some());
+        if let Some(value) = value {
+            write(self, value);
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader { bytes }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        if len > self.bytes.len() {
+            return Err(SaveStateError::Truncated);
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, SaveStateError> {
+        Ok(u128::from_be_bytes(self.array()?))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("flag")),
+        }
+    }
+
+    pub fn string(&mut self) -> Result<String, SaveStateError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| SaveStateError::Invalid("text"))
+    }
+
+    pub fn option<T>(
+        &mut self,
+        read: impl FnOnce(&mut StateReader<'a>) -> Result<T, SaveStateError>,
+    ) -> Result<Option<T>, SaveStateError> {
+        match self.bool()? {
+            true => read(self).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+// Wraps the payload in the header and the checksum
+pub fn encode(rom_hash: &str, payload: &[u8]) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.bytes(MAGIC);
+    writer.u16(VERSION);
+    writer.string(rom_hash);
+    writer.u32(payload.len() as u32);
+    writer.bytes(payload);
+
+    let mut bytes = writer.into_bytes();
+    let checksum = Sha1::digest(&bytes);
+    bytes.extend_from_slice(&checksum);
+    bytes
+}
+
+// Checks the header and the checksum, returns the payload
+pub fn decode<'a>(bytes: &'a [u8], rom_hash: &str) -> Result<&'a [u8], SaveStateError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(SaveStateError::NotASaveState);
+    }
+
+    let mut reader = StateReader::new(&bytes[MAGIC.len()..]);
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(SaveStateError::UnsupportedVersion(version));
+    }
+    if bytes.len() < MAGIC.len() + CHECKSUM_SIZE {
+        return Err(SaveStateError::Truncated);
+    }
+
+    let (data, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+    if Sha1::digest(data).as_slice() != checksum {
+        return Err(SaveStateError::ChecksumMismatch);
+    }
+
+    let found = reader.string()?;
+    if found != rom_hash {
+        return Err(SaveStateError::WrongRom {
+            expected: rom_hash.to_string(),
+            found,
+        });
+    }
+
+    let len = reader.u32()? as usize;
+    reader.bytes(len)
+}
+
+// Where the hotkey slots are saved:
+// ~/.local/share/chip8-emulator/states/<sha1>-<slot>.state
+// ($XDG_DATA_HOME is honoured)
+pub fn slot_path(rom_hash: &str, slot: u8) -> Option<PathBuf> {
+    let data_dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+
+    Some(
+        data_dir
+            .join("chip8-emulator")
+            .join("states")
+            .join(format!("{}-{}.state", rom_hash, slot)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut writer = StateWriter::new();
+        writer.u16(0x1234);
+        writer.option(Some(7), StateWriter::u8);
+        writer.option(None, StateWriter::u8);
+        writer.string("superchip");
+        let payload = writer.into_bytes();
+
+        let bytes = encode("abc", &payload);
+        let mut reader = StateReader::new(decode(&bytes, "abc").unwrap());
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.option(StateReader::u8), Ok(Some(7)));
+        assert_eq!(reader.option(StateReader::u8), Ok(None));
+        assert_eq!(reader.string(), Ok("superchip".to_string()));
+        assert!(reader.is_empty());
+        assert_eq!(reader.u8(), Err(SaveStateError::Truncated));
+    }
+
+    #[test]
+    fn rejected_states() {
+        let bytes = encode("abc", &[1, 2, 3]);
+        assert!(matches!(
+            decode(&bytes, "def"),
+            Err(SaveStateError::WrongRom { .. })
+        ));
+
+        let mut corrupted = bytes.clone();
+        corrupted[MAGIC.len() + 8] ^= 0xFF;
+        assert_eq!(
+            decode(&corrupted, "abc"),
+            Err(SaveStateError::ChecksumMismatch)
+        );
+
+        let mut newer = bytes.clone();
+        newer[MAGIC.len() + 1] += 1;
+        assert_eq!(
+            decode(&newer, "abc"),
+            Err(SaveStateError::UnsupportedVersion(VERSION + 1))
+        );
+
+        assert_eq!(decode(b"hello", "abc"), Err(SaveStateError::NotASaveState));
+    }
+}
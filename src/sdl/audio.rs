@@ -0,0 +1,79 @@
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::AudioSubsystem;
+
+use crate::frontend::Audio;
+
+const FREQUENCY: f32 = 440.0;
+
+// Square wave by default, or an XO-CHIP pattern once the ROM sets one
+pub struct Wave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    sample_rate: f32,
+    pattern: Option<[u8; 16]>,
+}
+
+impl AudioCallback for Wave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            let high = match &self.pattern {
+                Some(pattern) => {
+                    let bit = (self.phase * 128.0) as usize % 128;
+                    pattern[bit / 8] & (0x80 >> (bit % 8)) != 0
+                }
+                None => self.phase <= 0.5,
+            };
+            *x = if high { self.volume } else { -self.volume };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+pub struct SdlAudio {
+    device: AudioDevice<Wave>,
+}
+
+impl SdlAudio {
+    pub fn new(audio_subsystem: AudioSubsystem) -> Result<Self, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| Wave {
+            phase_inc: FREQUENCY / spec.freq as f32,
+            phase: 0.0,
+            volume: 0.25,
+            sample_rate: spec.freq as f32,
+            pattern: None,
+        })?;
+
+        device.pause();
+
+        Ok(Self { device })
+    }
+}
+
+impl Audio for SdlAudio {
+    fn start(&mut self) {
+        self.device.resume();
+    }
+
+    fn stop(&mut self) {
+        self.device.pause();
+    }
+
+    fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        let mut wave = self.device.lock();
+        wave.pattern = pattern.copied();
+        wave.phase_inc = match pattern {
+            // the phase covers the 128 samples of the pattern
+            Some(_) => 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0) / 128.0,
+            None => FREQUENCY,
+        } / wave.sample_rate;
+    }
+}
@@ -0,0 +1,147 @@
+use sdl2::{event::Event, keyboard::Keycode, EventPump};
+
+use crate::frontend::{Input, InputEvent};
+use crate::keypad::{Action, KeyHints};
+
+pub struct SdlInput {
+    event_pump: EventPump,
+    hints: KeyHints,
+}
+
+impl SdlInput {
+    pub fn new(event_pump: EventPump) -> SdlInput {
+        SdlInput {
+            event_pump,
+            hints: KeyHints::default(),
+        }
+    }
+
+    // Keyboard key bound to each action when the ROM has a hint for it
+    pub fn action_key_name(action: Action) -> &'static str {
+        match action {
+            Action::Up => "Up arrow",
+            Action::Down => "Down arrow",
+            Action::Left => "Left arrow",
+            Action::Right => "Right arrow",
+            Action::A => "Space",
+            Action::B => "Left Shift",
+            Action::Player2Up => "I",
+            Action::Player2Down => "K",
+        }
+    }
+
+    fn keycode_to_action(k: Keycode) -> Option<Action> {
+        match k {
+            Keycode::Up => Some(Action::Up),
+            Keycode::Down => Some(Action::Down),
+            Keycode::Left => Some(Action::Left),
+            Keycode::Right => Some(Action::Right),
+            Keycode::Space => Some(Action::A),
+            Keycode::LShift => Some(Action::B),
+            Keycode::I => Some(Action::Player2Up),
+            Keycode::K => Some(Action::Player2Down),
+            _ => None,
+        }
+    }
+
+    fn keycode_to_hinted_u8(&self, k: Keycode) -> Option<u8> {
+        SdlInput::keycode_to_action(k).and_then(|action| self.hints.key(action))
+    }
+
+    fn keycode_to_u8(k: Keycode) -> Option<u8> {
+        match k {
+            Keycode::Num1 => Some(0x1),
+            Keycode::Num2 => Some(0x2),
+            Keycode::Num3 => Some(0x3),
+            Keycode::Num4 => Some(0xC),
+            Keycode::Q => Some(0x4),
+            Keycode::W => Some(0x5),
+            Keycode::E => Some(0x6),
+            Keycode::R => Some(0xD),
+            Keycode::A => Some(0x7),
+            Keycode::S => Some(0x8),
+            Keycode::D => Some(0x9),
+            Keycode::F => Some(0xE),
+            Keycode::Z => Some(0xA),
+            Keycode::X => Some(0x0),
+            Keycode::C => Some(0xB),
+            Keycode::V => Some(0xF),
+            _ => None,
+        }
+    }
+
+    // The CHIP-8X second keypad sits on the numeric keypad, in the same layout
+    fn keycode_to_second_u8(k: Keycode) -> Option<u8> {
+        match k {
+            Keycode::Kp7 => Some(0x1),
+            Keycode::Kp8 => Some(0x2),
+            Keycode::Kp9 => Some(0x3),
+            Keycode::KpDivide => Some(0xC),
+            Keycode::Kp4 => Some(0x4),
+            Keycode::Kp5 => Some(0x5),
+            Keycode::Kp6 => Some(0x6),
+            Keycode::KpMultiply => Some(0xD),
+            Keycode::Kp1 => Some(0x7),
+            Keycode::Kp2 => Some(0x8),
+            Keycode::Kp3 => Some(0x9),
+            Keycode::KpMinus => Some(0xE),
+            Keycode::Kp0 => Some(0xA),
+            Keycode::KpPeriod => Some(0x0),
+            Keycode::KpEnter => Some(0xB),
+            Keycode::KpPlus => Some(0xF),
+            _ => None,
+        }
+    }
+}
+
+impl Input for SdlInput {
+    fn poll_event(&mut self) -> Option<InputEvent> {
+        while let Some(event) = self.event_pump.poll_event() {
+            let translated = match event {
+                Event::Quit { .. } => Some(InputEvent::Quit),
+                // hinted keys win, so the arrows only change the speed
+                // when the ROM doesn't use them
+                Event::KeyDown {
+                    keycode: Some(k), ..
+                } => match (self.keycode_to_hinted_u8(k), k) {
+                    (Some(key), _) => Some(InputEvent::KeyDown(key)),
+                    (None, Keycode::P) => Some(InputEvent::Pause),
+                    (None, Keycode::F5) => Some(InputEvent::SaveState),
+                    (None, Keycode::F9) => Some(InputEvent::LoadState),
+                    (None, Keycode::F6) => Some(InputEvent::ChangeSlot(-1)),
+                    (None, Keycode::F7) => Some(InputEvent::ChangeSlot(1)),
+                    (None, Keycode::Backspace) => Some(InputEvent::Rewind(true)),
+                    (None, Keycode::Up) => Some(InputEvent::AdjustSpeed(600)),
+                    (None, Keycode::Down) => Some(InputEvent::AdjustSpeed(-600)),
+                    (None, Keycode::Right) => Some(InputEvent::AdjustSpeed(60)),
+                    (None, Keycode::Left) => Some(InputEvent::AdjustSpeed(-60)),
+                    _ => SdlInput::keycode_to_u8(k)
+                        .map(InputEvent::KeyDown)
+                        .or(SdlInput::keycode_to_second_u8(k).map(InputEvent::SecondKeyDown)),
+                },
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => Some(InputEvent::Rewind(false)),
+                Event::KeyUp {
+                    keycode: Some(k), ..
+                } => self
+                    .keycode_to_hinted_u8(k)
+                    .or(SdlInput::keycode_to_u8(k))
+                    .map(InputEvent::KeyUp)
+                    .or(SdlInput::keycode_to_second_u8(k).map(InputEvent::SecondKeyUp)),
+                _ => None,
+            };
+
+            if translated.is_some() {
+                return translated;
+            }
+        }
+
+        None
+    }
+
+    fn set_key_hints(&mut self, hints: &KeyHints) {
+        self.hints = hints.clone();
+    }
+}
@@ -0,0 +1,30 @@
+// SDL2 implementation of the frontend traits
+mod audio;
+mod input;
+mod video;
+
+pub use audio::SdlAudio;
+pub use input::SdlInput;
+pub use video::SdlVideo;
+
+pub fn init(title: &str) -> Result<(SdlVideo, SdlInput, SdlAudio), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let audio_subsystem = sdl_context.audio()?;
+
+    let window = video_subsystem
+        .window(title, 800, 600)
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let event_pump = sdl_context.event_pump()?;
+
+    Ok((
+        SdlVideo::new(canvas),
+        SdlInput::new(event_pump),
+        SdlAudio::new(audio_subsystem)?,
+    ))
+}
@@ -0,0 +1,96 @@
+use sdl2::render::WindowCanvas;
+
+use sdl2::pixels::Color;
+
+use crate::display::{Display, Rgb};
+use crate::frontend::Video;
+
+pub struct SdlVideo {
+    canvas: WindowCanvas,
+}
+
+impl SdlVideo {
+    // VP-590 colours, used by CHIP-8X programs
+    const CHIP8X_PALETTE: [(u8, u8, u8); 8] = [
+        (0, 0, 0),
+        (255, 0, 0),
+        (0, 0, 255),
+        (255, 0, 255),
+        (0, 255, 0),
+        (255, 255, 0),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+
+    pub fn new(canvas: WindowCanvas) -> SdlVideo {
+        SdlVideo { canvas }
+    }
+}
+
+impl From<Rgb> for Color {
+    fn from(rgb: Rgb) -> Self {
+        Color::RGB(rgb.0, rgb.1, rgb.2)
+    }
+}
+
+impl Video for SdlVideo {
+    fn render(&mut self, display: &Display) {
+        let palette = display.palette();
+        self.canvas.set_draw_color(display.border_color());
+        self.canvas.clear();
+
+        let window_width = self.canvas.window().size().0 as usize;
+        let window_height = self.canvas.window().size().1 as usize;
+        let (width, height) = (display.width(), display.height());
+        let block = std::cmp::min(window_width / width, window_height / height);
+        let start_x = (window_width - width * block) / 2;
+        let start_y = (window_height - height * block) / 2;
+        let area = sdl2::rect::Rect::new(
+            start_x as i32,
+            start_y as i32,
+            (width * block) as u32,
+            (height * block) as u32,
+        );
+
+        let layer = display.color_layer();
+        self.canvas.set_draw_color(match layer {
+            Some(layer) => SdlVideo::CHIP8X_PALETTE[layer.background() as usize].into(),
+            None => Color::from(palette.pixels[0]),
+        });
+        self.canvas.fill_rect(area).unwrap();
+
+        for y in 0..height {
+            for x in 0..width {
+                let color = display.color(x, y);
+                if color != 0 {
+                    self.canvas.set_draw_color(match layer {
+                        Some(layer) => {
+                            SdlVideo::CHIP8X_PALETTE[layer.foreground(x, y) as usize].into()
+                        }
+                        None => Color::from(palette.pixels[color as usize]),
+                    });
+                    self.canvas
+                        .fill_rect(sdl2::rect::Rect::new(
+                            (start_x + x * block) as i32,
+                            (start_y + y * block) as i32,
+                            block as u32,
+                            block as u32,
+                        ))
+                        .unwrap();
+                }
+            }
+        }
+
+        self.canvas.set_draw_color(palette.pixels[1]);
+        self.canvas
+            .draw_rect(sdl2::rect::Rect::new(
+                start_x as i32 - 1,
+                start_y as i32 - 1,
+                (width * block + 2) as u32,
+                (height * block + 2) as u32,
+            ))
+            .unwrap();
+
+        self.canvas.present();
+    }
+}
@@ -0,0 +1,254 @@
+// Execution traces, one line per instruction with the state it ran in:
+//
+// 0000000042 0206 7001 V=0100000000000000000000000000000F I=0300 SP=1 DT=00 ST=00 # v0 += 0x01
+//
+// The cycle count comes first so traces of different emulators can be
+// compared line by line, see diff.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::chip::Chip;
+use crate::debugger::parse_address;
+use crate::disasm;
+
+// Which instructions get traced, everything by default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub addresses: Option<RangeInclusive<u16>>,
+    pub frames: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, pc: u16, frame: u64) -> bool {
+        self.addresses.as_ref().is_none_or(|a| a.contains(&pc))
+            && self.frames.as_ref().is_none_or(|f| f.contains(&frame))
+    }
+
+    // Hex addresses, "200-2ff"
+    pub fn parse_addresses(s: &str) -> Result<RangeInclusive<u16>, String> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let (start, end) = (parse_address(start)?, parse_address(end)?);
+        if start > end {
+            return Err(format!(
+                "Invalid address range '{}', the end comes first",
+                s
+            ));
+        }
+        Ok(start..=end)
+    }
+
+    // Decimal frames, "60-120"; "60-" runs to the end
+    pub fn parse_frames(s: &str) -> Result<RangeInclusive<u64>, String> {
+        let parse =
+            |frame: &str| u64::from_str(frame).map_err(|_| format!("Invalid frame '{}'", frame));
+        match s.split_once('-') {
+            Some((start, "")) => Ok(parse(start)?..=u64::MAX),
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("Invalid frame range '{}', the end comes first", s));
+                }
+                Ok(start..=end)
+            }
+            None => Ok(parse(s)?..=parse(s)?),
+        }
+    }
+}
+
+pub struct Tracer {
+    out: Box<dyn Write>,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, filter: TraceFilter) -> Tracer {
+        Tracer { out, filter }
+    }
+
+    pub fn create(path: &Path, filter: TraceFilter) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(Box::new(BufWriter::new(file)), filter))
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.out, "{}", line)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// The chip state before the instruction at pc runs
+pub fn line(chip: &Chip, cycle: u64, pc: u16, opcode: u16) -> String {
+    let mnemonic = chip
+        .memory()
+        .get(pc as usize..)
+        .and_then(|code| disasm::decode(code, chip.config().instruction_set))
+        .map(|(text, _)| text)
+        .unwrap_or_else(|| "(unknown)".to_string());
+
+    let mut registers = String::new();
+    for v in chip.registers() {
+        let _ = write!(registers, "{:02X}", v);
+    }
+
+    format!(
+        "{:010} {:04X} {:04X} V={} I={:04X} SP={:X} DT={:02X} ST={:02X} # {}",
+        cycle,
+        pc,
+        opcode,
+        registers,
+        chip.i(),
+        chip.sp(),
+        chip.delay_timer(),
+        chip.sound_timer(),
+        mnemonic
+    )
+}
+
+// Where two traces stop agreeing. A None line means that trace ended first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub cycle: u64,
+    pub left: Option<String>,
+    pub right: Option<String>,
+}
+
+// Finds the first cycle the traces differ on. Mnemonics are ignored, so
+// traces from emulators that disassemble differently still compare.
+pub fn diff(left: impl BufRead, right: impl BufRead) -> io::Result<Option<Divergence>> {
+    let state = |line: &str| {
+        line.split(" # ")
+            .next()
+            .unwrap_or("")
+            .trim_end()
+            .to_string()
+    };
+    let cycle = |line: &Option<String>| {
+        line.as_deref()
+            .and_then(|line| line.split_whitespace().next())
+            .and_then(|cycle| cycle.parse().ok())
+    };
+
+    let mut left = left.lines();
+    let mut right = right.lines();
+    let mut line_number = 0;
+    loop {
+        let (l, r) = (left.next().transpose()?, right.next().transpose()?);
+        if l.is_none() && r.is_none() {
+            return Ok(None);
+        }
+        if l.as_deref().map(state) != r.as_deref().map(state) {
+            return Ok(Some(Divergence {
+                cycle: cycle(&l).or(cycle(&r)).unwrap_or(line_number),
+                left: l,
+                right: r,
+            }));
+        }
+        line_number += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Write handle the test keeps a copy of
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_lines() {
+        // V0 = 5; I = 0x300; loop: jump loop
+        let mut chip = Chip::new();
+        chip.load_rom(&[0x60, 0x05, 0xA3, 0x00, 0x12, 0x04])
+            .unwrap();
+        let out = Shared::default();
+        let filter = TraceFilter {
+            addresses: Some(TraceFilter::parse_addresses("202-204").unwrap()),
+            frames: Some(TraceFilter::parse_frames("0").unwrap()),
+        };
+        chip.set_tracer(Some(Tracer::new(Box::new(out.clone()), filter)));
+        chip.step_frame(0).unwrap();
+        chip.step_frame(0).unwrap();
+
+        let trace = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines[0],
+            "0000000001 0202 A300 V=05000000000000000000000000000000 I=0000 SP=0 DT=00 ST=00 # i := 0x300"
+        );
+        assert!(lines[1].starts_with("0000000002 0204 1204 "));
+        // only the first frame, where the jump runs many times
+        assert_eq!(lines.len() as u64, chip.cycle_count() / 2 - 1);
+    }
+
+    struct Full;
+
+    impl Write for Full {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::StorageFull, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_errors_blame_the_trace() {
+        let mut chip = Chip::new();
+        chip.load_rom(&[0x12, 0x00]).unwrap();
+        chip.set_tracer(Some(Tracer::new(Box::new(Full), TraceFilter::default())));
+        let error = chip.step_frame(0).unwrap_err();
+        assert_eq!(error.to_string(), "Error writing the trace file: disk full");
+    }
+
+    #[test]
+    fn reversed_ranges() {
+        assert_eq!(
+            TraceFilter::parse_addresses("0x200-0x2ff"),
+            Ok(0x200..=0x2FF)
+        );
+        assert!(TraceFilter::parse_addresses("0x300-0x200").is_err());
+        assert!(TraceFilter::parse_frames("120-60").is_err());
+    }
+
+    #[test]
+    fn diff_traces() {
+        let a = "0000000000 0200 6005 V=00 # v0 := 5\n0000000001 0202 A300 V=05 # i := 0x300\n";
+        let b = "0000000000 0200 6005 V=00 # v0 := 0x05\n0000000001 0202 A300 V=06 # i := 0x300\n";
+        assert_eq!(diff(a.as_bytes(), a.as_bytes()).unwrap(), None);
+
+        let divergence = diff(a.as_bytes(), b.as_bytes()).unwrap().unwrap();
+        assert_eq!(divergence.cycle, 1);
+        assert!(divergence.right.unwrap().contains("V=06"));
+
+        let first_line = &b[..=b.find('\n').unwrap()];
+        let short = diff(a.as_bytes(), first_line.as_bytes()).unwrap().unwrap();
+        assert_eq!(short.cycle, 1);
+        assert_eq!(short.right, None);
+    }
+}
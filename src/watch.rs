@@ -0,0 +1,202 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::debugger::parse_address;
+
+// Which memory accesses a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    pub fn matches(self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+impl FromStr for Access {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "r" | "read" => Ok(Access::Read),
+            "w" | "write" => Ok(Access::Write),
+            "rw" | "readwrite" => Ok(Access::ReadWrite),
+            _ => Err(format!("Unknown access '{}', expected r, w or rw", s)),
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Read => write!(f, "read"),
+            Access::Write => write!(f, "write"),
+            Access::ReadWrite => write!(f, "read/write"),
+        }
+    }
+}
+
+// Registers are watched for changes, memory for the accesses that match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    // start and end included
+    Memory {
+        start: u16,
+        end: u16,
+        access: Access,
+    },
+    Register(u8),
+    I,
+}
+
+impl Watchpoint {
+    pub fn watches_memory(&self, address: u16, write: bool) -> bool {
+        match *self {
+            Watchpoint::Memory { start, end, access } => {
+                (start..=end).contains(&address) && access.matches(write)
+            }
+            _ => false,
+        }
+    }
+}
+
+// "v3", "i", "300" or "300-30f", memory optionally followed by r, w or rw.
+// Memory is watched for writes by default.
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let target = words.next().ok_or("Expected a register or an address")?;
+        let access = words.next().map(str::parse).transpose()?;
+        if words.next().is_some() {
+            return Err(format!("Unexpected text after '{}'", s.trim()));
+        }
+
+        let lower = target.to_lowercase();
+        let register = lower
+            .strip_prefix('v')
+            .filter(|digit| digit.len() == 1)
+            .and_then(|digit| u8::from_str_radix(digit, 16).ok());
+
+        match (lower.as_str(), register, access) {
+            ("i", _, None) => Ok(Watchpoint::I),
+            (_, Some(x), None) => Ok(Watchpoint::Register(x)),
+            ("i", _, Some(_)) | (_, Some(_), Some(_)) => {
+                Err("Registers are watched for changes, without an access".to_string())
+            }
+            _ => {
+                let (start, end) = match target.split_once('-') {
+                    Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+                    None => (parse_address(target)?, parse_address(target)?),
+                };
+                if end < start {
+                    return Err(format!("Empty address range '{}'", target));
+                }
+                Ok(Watchpoint::Memory {
+                    start,
+                    end,
+                    access: access.unwrap_or(Access::Write),
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory { start, end, access } if start == end => {
+                write!(f, "{:#06X} ({})", start, access)
+            }
+            Watchpoint::Memory { start, end, access } => {
+                write!(f, "{:#06X}-{:#06X} ({})", start, end, access)
+            }
+            Watchpoint::Register(x) => write!(f, "V{:X}", x),
+            Watchpoint::I => write!(f, "I"),
+        }
+    }
+}
+
+// What a watchpoint caught
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Memory(u16),
+    Register(u8),
+    I,
+}
+
+// Recorded by the chip for the last instruction it ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub location: Location,
+    // address of the instruction
+    pub pc: u16,
+    pub write: bool,
+    // the same for reads
+    pub old: u16,
+    pub new: u16,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Location::Memory(address) if self.write => write!(
+                f,
+                "{:#06X} written: {:#04X} -> {:#04X}",
+                address, self.old, self.new
+            ),
+            Location::Memory(address) => write!(f, "{:#06X} read: {:#04X}", address, self.new),
+            Location::Register(x) => {
+                write!(
+                    f,
+                    "V{:X} changed: {:#04X} -> {:#04X}",
+                    x, self.old, self.new
+                )
+            }
+            Location::I => write!(f, "I changed: {:#06X} -> {:#06X}", self.old, self.new),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_watchpoints() {
+        assert_eq!("vA".parse(), Ok(Watchpoint::Register(0xA)));
+        assert_eq!("i".parse(), Ok(Watchpoint::I));
+        assert_eq!(
+            "300-0x30f rw".parse(),
+            Ok(Watchpoint::Memory {
+                start: 0x300,
+                end: 0x30F,
+                access: Access::ReadWrite
+            })
+        );
+        assert_eq!(
+            "0x300".parse::<Watchpoint>().unwrap().to_string(),
+            "0x0300 (write)"
+        );
+        assert!("v3 r".parse::<Watchpoint>().is_err());
+        assert!("310-300".parse::<Watchpoint>().is_err());
+        assert!("300 x".parse::<Watchpoint>().is_err());
+    }
+
+    #[test]
+    fn access() {
+        let watchpoint: Watchpoint = "300-301 r".parse().unwrap();
+        assert!(watchpoint.watches_memory(0x301, false));
+        assert!(!watchpoint.watches_memory(0x301, true));
+        assert!(!watchpoint.watches_memory(0x302, false));
+    }
+}
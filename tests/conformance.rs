@@ -0,0 +1,242 @@
+// Runs the test ROMs in tests_roms/ without a window and compares the screen
+// they end on with the images in tests/golden/. After a deliberate change to
+// what a ROM shows, regenerate the images with
+//
+//     UPDATE_GOLDEN=1 cargo test --no-default-features --test conformance
+//
+// and check the new ones before committing them.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use chip8_emulator::chip::Chip;
+use chip8_emulator::config::Config;
+use chip8_emulator::display::Display;
+use chip8_emulator::frontend::InputEvent;
+
+// Frames a key is held for when a script presses it
+const PRESS_FRAMES: u64 = 3;
+
+// A ROM run: the platform to force (else the database decides) and the
+// inputs to apply before the given frames
+struct Run {
+    rom: &'static str,
+    platform: Option<&'static str>,
+    inputs: Vec<(u64, InputEvent)>,
+    frames: u64,
+}
+
+impl Run {
+    fn new(rom: &'static str, frames: u64) -> Run {
+        Run {
+            rom,
+            platform: None,
+            inputs: Vec::new(),
+            frames,
+        }
+    }
+
+    fn platform(mut self, id: &'static str) -> Run {
+        self.platform = Some(id);
+        self
+    }
+
+    // Presses and releases the key
+    fn press(self, frame: u64, key: u8) -> Run {
+        self.key_down(frame, key).key_up(frame + PRESS_FRAMES, key)
+    }
+
+    fn key_down(mut self, frame: u64, key: u8) -> Run {
+        self.inputs.push((frame, InputEvent::KeyDown(key)));
+        self
+    }
+
+    fn key_up(mut self, frame: u64, key: u8) -> Run {
+        self.inputs.push((frame, InputEvent::KeyUp(key)));
+        self
+    }
+
+    fn chip(&self) -> Chip {
+        let mut config = Config::new();
+        config.seed = Some(0);
+        if let Some(platform) = self.platform {
+            config.force_platform(platform).unwrap();
+        }
+        let mut chip = Chip::with_config(config);
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests_roms")
+            .join(self.rom);
+        chip.load_rom(&fs::read(path).unwrap()).unwrap();
+        chip
+    }
+
+    // Runs every frame and returns the chip and whether the sound played on
+    // each of them
+    fn run(&self) -> (Chip, Vec<bool>) {
+        let mut chip = self.chip();
+        let mut sound = Vec::new();
+        for frame in 0..self.frames {
+            for &(_, event) in self.inputs.iter().filter(|(at, _)| *at == frame) {
+                chip.handle_input(event);
+            }
+            sound.push(chip.next_frame().unwrap().sound);
+        }
+        (chip, sound)
+    }
+}
+
+// One character per pixel: '.' off, '#' on, 'o' on the second XO-CHIP plane
+// only and '@' on both
+fn render(display: &Display) -> String {
+    let mut image = String::new();
+    for y in 0..display.height() {
+        for x in 0..display.width() {
+            image.push(match display.color(x, y) {
+                0 => '.',
+                1 => '#',
+                2 => 'o',
+                _ => '@',
+            });
+        }
+        image.push('\n');
+    }
+    image
+}
+
+fn check_golden(name: &str, display: &Display) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.txt", name));
+    let image = render(display);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &image).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("can't read {}: {}", path.display(), e));
+    assert!(
+        image == golden,
+        "{} doesn't match {}\nexpected:\n{}\nfound:\n{}",
+        name,
+        path.display(),
+        golden,
+        image
+    );
+}
+
+fn check(name: &str, run: Run) -> Vec<bool> {
+    let (chip, sound) = run.run();
+    check_golden(name, chip.display());
+    sound
+}
+
+#[test]
+fn chip8_logo() {
+    check("1-chip8-logo", Run::new("1-chip8-logo.ch8", 60));
+}
+
+#[test]
+fn ibm_logo() {
+    check("2-ibm-logo", Run::new("2-ibm-logo.ch8", 60));
+}
+
+#[test]
+fn corax_plus() {
+    check("3-corax+", Run::new("3-corax+.ch8", 120));
+}
+
+#[test]
+fn flags() {
+    check("4-flags", Run::new("4-flags.ch8", 120));
+}
+
+// The menu asks for the platform to test: 1 CHIP-8, 2 SUPER-CHIP (then 1
+// modern or 2 legacy) and 3 XO-CHIP. Every quirk should get a check mark.
+#[test]
+fn quirks_chip8() {
+    let run = Run::new("5-quirks.ch8", 1200)
+        .platform("originalChip8")
+        .press(60, 1);
+    check("5-quirks-chip8", run);
+}
+
+#[test]
+fn quirks_superchip() {
+    let run = Run::new("5-quirks.ch8", 1200)
+        .platform("superchip")
+        .press(60, 2)
+        .press(80, 1);
+    check("5-quirks-superchip", run);
+}
+
+#[test]
+fn quirks_xochip() {
+    let run = Run::new("5-quirks.ch8", 1200)
+        .platform("xochip")
+        .press(60, 3);
+    check("5-quirks-xochip", run);
+}
+
+// Menu 1 tests Ex9E: the held key is highlighted on the keypad
+#[test]
+fn keypad_ex9e() {
+    let run = Run::new("6-keypad.ch8", 200)
+        .press(60, 1)
+        .key_down(100, 0xA);
+    check("6-keypad-ex9e", run);
+}
+
+// Menu 3 tests Fx0A: it only returns once the key is released
+#[test]
+fn keypad_fx0a() {
+    let run = Run::new("6-keypad.ch8", 200).press(60, 3).press(100, 5);
+    check("6-keypad-fx0a", run);
+}
+
+// The ROM beeps a few times on start, then key B beeps for as long as it's held
+#[test]
+fn beep() {
+    let sound = check(
+        "7-beep",
+        Run::new("7-beep.ch8", 140)
+            .key_down(40, 0xB)
+            .key_up(80, 0xB),
+    );
+    assert!(sound[..12].iter().any(|&on| on));
+    assert!(sound[45..80].iter().all(|&on| on));
+    assert!(!sound[90..].iter().any(|&on| on));
+}
+
+// SCTEST stops at the first test that fails and draws ERROR and its number in
+// the top left corner. It expects Fx1E to set VF when I goes past 0xFFF,
+// which none of the platforms do, so it stops at error 24 for now.
+const SCTEST_ERROR: [&str; 5] = [
+    "####.###...###...####.###.",
+    "#....#..#..#..#..#..#.#..#",
+    "####.###...###...#..#.###.",
+    "#....#..#..#..#..#..#.#..#",
+    "####.#...#.#...#.####.#...",
+];
+
+#[test]
+#[ignore = "SCTEST stops at error 24, Fx1E doesn't set VF past 0xFFF"]
+fn sctest() {
+    let (chip, _) = Run::new("SCTEST.CH8", 120).platform("superchip").run();
+    let image = render(chip.display());
+    let corner: Vec<&str> = image
+        .lines()
+        .take(SCTEST_ERROR.len())
+        .map(|line| &line[..SCTEST_ERROR[0].len()])
+        .collect();
+    assert!(corner != SCTEST_ERROR, "SCTEST failed:\n{}", image);
+}
+
+#[test]
+fn chip8_test_rom() {
+    check("chip8-test-rom", Run::new("chip8-test-rom.ch8", 120));
+}